@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Expected sha256 of downloads, for --verify-against
+#[derive(Default)]
+pub struct Checksums {
+    digests: HashMap<Url, String>,
+}
+
+impl Checksums {
+    // Reads `sha256  url` lines, as written by sha256sum with urls for names;
+    // blank lines and `#` comments are skipped
+    pub fn from_file(path: &str) -> Result<Self> {
+        let list = std::fs::read_to_string(path).with_context(|| format!("can't read {}", path))?;
+        Self::parse(&list).with_context(|| format!("invalid checksum list {}", path))
+    }
+
+    fn parse(list: &str) -> Result<Self> {
+        let mut digests = HashMap::new();
+        for line in list.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (digest, url) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| anyhow!("expected `sha256  url`, got {:?}", line))?;
+            if digest.len() != 64 || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(anyhow!("invalid sha256 {:?}", digest));
+            }
+            // sha256sum marks binary mode with a `*` before the name
+            let url = url.trim_start().trim_start_matches('*');
+            let url = Url::parse(url).with_context(|| format!("invalid url {:?}", url))?;
+            digests.insert(url, digest.to_ascii_lowercase());
+        }
+        Ok(Self { digests })
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        self.digests.contains_key(url)
+    }
+
+    // Fails when `url` is listed with another digest than the one of `body`
+    pub fn verify(&self, url: &Url, body: &[u8]) -> Result<()> {
+        let expected = match self.digests.get(url) {
+            Some(expected) => expected,
+            None => return Ok(()),
+        };
+        let actual = sha256_hex(body);
+        if actual != *expected {
+            return Err(anyhow!(
+                "checksum mismatch for {} : expected {}, got {}",
+                url,
+                expected,
+                actual
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOD: &[u8] = b"hello\n";
+    // sha256sum of GOOD
+    const GOOD_SHA256: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_sha256sum_lines() {
+        let list = format!(
+            "# mirror digests\n\n{}  https://a.example/hello.txt\n{} *https://a.example/bin\n",
+            GOOD_SHA256,
+            GOOD_SHA256.to_ascii_uppercase()
+        );
+        let checksums = Checksums::parse(&list).unwrap();
+        assert!(checksums.contains(&url("https://a.example/hello.txt")));
+        assert!(checksums.contains(&url("https://a.example/bin")));
+        assert!(!checksums.contains(&url("https://a.example/other")));
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        assert!(Checksums::parse("https://a.example/hello.txt").is_err());
+        assert!(Checksums::parse("abc  https://a.example/hello.txt").is_err());
+        assert!(Checksums::parse(&format!("{}  not a url", GOOD_SHA256)).is_err());
+    }
+
+    #[test]
+    fn verifies_known_good_and_wrong_hashes() {
+        let wrong = "0".repeat(64);
+        let list = format!(
+            "{}  https://a.example/good\n{}  https://a.example/bad\n",
+            GOOD_SHA256, wrong
+        );
+        let checksums = Checksums::parse(&list).unwrap();
+        assert_eq!(sha256_hex(GOOD), GOOD_SHA256);
+        checksums
+            .verify(&url("https://a.example/good"), GOOD)
+            .unwrap();
+        let err = checksums
+            .verify(&url("https://a.example/bad"), GOOD)
+            .unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+        // unlisted urls aren't checked
+        checksums
+            .verify(&url("https://a.example/unlisted"), b"anything")
+            .unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkMode {
+    Hardlink,
+    Symlink,
+    Copy,
+}
+
+impl FromStr for LinkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "hardlink" => Ok(Self::Hardlink),
+            "symlink" => Ok(Self::Symlink),
+            "copy" => Ok(Self::Copy),
+            _ => Err(anyhow!("unknown dedup link mode: {}", s)),
+        }
+    }
+}
+
+// Remembers where each distinct body was first saved during a run
+#[derive(Default)]
+pub struct Dedup {
+    saved: Mutex<HashMap<Vec<u8>, PathBuf>>,
+}
+
+impl Dedup {
+    // Path an identical body was already saved to, other than `path`
+    pub fn original(&self, body: &[u8], path: &Path) -> Option<PathBuf> {
+        let digest = Sha256::digest(body).to_vec();
+        let saved = self.saved.lock().unwrap();
+        saved.get(&digest).filter(|p| *p != path).cloned()
+    }
+
+    // Records that `body` has been written to `path`
+    pub fn saved(&self, body: &[u8], path: &Path) {
+        let digest = Sha256::digest(body).to_vec();
+        self.saved
+            .lock()
+            .unwrap()
+            .entry(digest)
+            .or_insert_with(|| path.to_owned());
+    }
+}
+
+#[cfg(unix)]
+async fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    let target = crate::output::relative_path(link, original);
+    fs::symlink(target, link).await
+}
+
+#[cfg(not(unix))]
+async fn symlink(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+// Makes `link` a duplicate of `original`, falling back from hardlinks to
+// symlinks to plain copies when the filesystem doesn't support one
+pub async fn link(original: &Path, link: &Path, mode: LinkMode) -> Result<()> {
+    if fs::symlink_metadata(link).await.is_ok() {
+        fs::remove_file(link).await?;
+    }
+    if mode == LinkMode::Hardlink {
+        match fs::hard_link(original, link).await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("can't hardlink {:?}: {}, trying a symlink", link, e),
+        }
+    }
+    if mode != LinkMode::Copy {
+        match symlink(original, link).await {
+            Ok(()) => return Ok(()),
+            Err(e) => warn!("can't symlink {:?}: {}, copying instead", link, e),
+        }
+    }
+    info!("copying duplicate {:?} from {:?}", link, original);
+    fs::copy(original, link).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory of the test's own
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rget-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn remembers_the_first_path() {
+        let dedup = Dedup::default();
+        let (a, b) = (Path::new("a.html"), Path::new("b.html"));
+        assert_eq!(dedup.original(b"body", a), None);
+
+        dedup.saved(b"body", a);
+        dedup.saved(b"body", b);
+        assert_eq!(dedup.original(b"body", b), Some(a.to_owned()));
+        // a body isn't a duplicate of itself
+        assert_eq!(dedup.original(b"body", a), None);
+        assert_eq!(dedup.original(b"other", b), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn hardlinks() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempdir("dedup-hardlink");
+        let (original, copy) = (dir.join("a.html"), dir.join("b.html"));
+        std::fs::write(&original, "body").unwrap();
+        link(&original, &copy, LinkMode::Hardlink).await.unwrap();
+
+        let meta = std::fs::symlink_metadata(&copy).unwrap();
+        assert_eq!(meta.ino(), std::fs::metadata(&original).unwrap().ino());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_relative_to_the_link() {
+        let dir = tempdir("dedup-symlink");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let (original, copy) = (dir.join("a.html"), dir.join("sub").join("b.html"));
+        std::fs::write(&original, "body").unwrap();
+        link(&original, &copy, LinkMode::Symlink).await.unwrap();
+
+        assert_eq!(
+            std::fs::read_link(&copy).unwrap(),
+            Path::new("..").join("a.html")
+        );
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "body");
+    }
+
+    #[tokio::test]
+    async fn copies() {
+        let dir = tempdir("dedup-copy");
+        let (original, copy) = (dir.join("a.html"), dir.join("b.html"));
+        std::fs::write(&original, "body").unwrap();
+        link(&original, &copy, LinkMode::Copy).await.unwrap();
+
+        assert!(std::fs::symlink_metadata(&copy).unwrap().is_file());
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "body");
+        // the copy doesn't change with the original
+        std::fs::write(&original, "changed").unwrap();
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "body");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn falls_back_to_a_symlink() {
+        // directories can't be hardlinked
+        let dir = tempdir("dedup-fallback");
+        let (original, copy) = (dir.join("a"), dir.join("b"));
+        std::fs::create_dir_all(&original).unwrap();
+        link(&original, &copy, LinkMode::Hardlink).await.unwrap();
+
+        assert!(std::fs::symlink_metadata(&copy).unwrap().is_symlink());
+    }
+
+    #[cfg(not(unix))]
+    #[tokio::test]
+    async fn falls_back_to_a_copy() {
+        let dir = tempdir("dedup-fallback");
+        let (original, copy) = (dir.join("a.html"), dir.join("b.html"));
+        std::fs::write(&original, "body").unwrap();
+        link(&original, &copy, LinkMode::Symlink).await.unwrap();
+
+        assert!(std::fs::symlink_metadata(&copy).unwrap().is_file());
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "body");
+    }
+
+    #[tokio::test]
+    async fn replaces_an_existing_file() {
+        let dir = tempdir("dedup-replace");
+        let (original, copy) = (dir.join("a.html"), dir.join("b.html"));
+        std::fs::write(&original, "body").unwrap();
+        for mode in [LinkMode::Hardlink, LinkMode::Symlink, LinkMode::Copy] {
+            std::fs::write(&copy, "stale").unwrap();
+            link(&original, &copy, mode).await.unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&copy).unwrap(),
+                "body",
+                "{:?}",
+                mode
+            );
+            // so the next "stale" doesn't write through a link to the original
+            std::fs::remove_file(&copy).unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&original).unwrap(), "body");
+    }
+}
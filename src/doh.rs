@@ -0,0 +1,91 @@
+// Resolves host names with DNS over HTTPS (RFC 8484) instead of the system
+// resolver. Only available with the `doh` cargo feature.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hickory_proto::{
+    op::{Message, Query},
+    rr::{Name, RData, RecordType},
+};
+// what reqwest hands resolvers, from the hyper it is built on
+use hyper::client::connect::dns::Name as HostName;
+use reqwest::{
+    dns::{Addrs, Resolve, Resolving},
+    header::ACCEPT,
+    Client, Url,
+};
+use tracing::debug;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+
+pub struct DohResolver {
+    // a client of its own, using the system resolver for the endpoint's name
+    client: Client,
+    endpoint: Url,
+}
+
+impl DohResolver {
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let endpoint = Url::parse(endpoint)?;
+        Ok(Self {
+            client: Client::builder().use_rustls_tls().build()?,
+            endpoint,
+        })
+    }
+}
+
+impl Resolve for DohResolver {
+    fn resolve(&self, name: HostName) -> Resolving {
+        let client = self.client.clone();
+        let endpoint = self.endpoint.clone();
+        let host = name.as_str().to_owned();
+        Box::pin(async move {
+            let mut ips = lookup(&client, &endpoint, &host, RecordType::A).await?;
+            ips.extend(lookup(&client, &endpoint, &host, RecordType::AAAA).await?);
+            if ips.is_empty() {
+                return Err(anyhow!("no address for {} from {}", host, endpoint).into());
+            }
+            debug!("{} resolved to {:?} by {}", host, ips, endpoint);
+            // the port is set by the connector
+            let addrs: Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
+
+// Asks `endpoint` for the `kind` records of `host` with a GET request
+async fn lookup(
+    client: &Client,
+    endpoint: &Url,
+    host: &str,
+    kind: RecordType,
+) -> Result<Vec<IpAddr>> {
+    let mut query = Message::new();
+    query
+        .set_recursion_desired(true)
+        .add_query(Query::query(Name::from_str(host)?, kind));
+    let mut url = endpoint.clone();
+    url.query_pairs_mut()
+        .append_pair("dns", &URL_SAFE_NO_PAD.encode(query.to_vec()?));
+    let resp = client
+        .get(url)
+        .header(ACCEPT, DNS_MESSAGE)
+        .send()
+        .await?
+        .error_for_status()?;
+    let answer = Message::from_vec(&resp.bytes().await?)?;
+    Ok(answer
+        .answers()
+        .iter()
+        .filter_map(|record| match record.data()? {
+            RData::A(a) => Some(IpAddr::V4(a.0)),
+            RData::AAAA(aaaa) => Some(IpAddr::V6(aaaa.0)),
+            _ => None,
+        })
+        .collect())
+}
@@ -0,0 +1,90 @@
+use std::{fmt, io, path::PathBuf};
+
+use reqwest::{StatusCode, Url};
+
+// Why a task failed, for callers that care about more than the message
+#[derive(Debug)]
+pub enum FetchError {
+    Http {
+        url: Url,
+        status: StatusCode,
+    },
+    Io {
+        url: Url,
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        url: Url,
+        source: tl::errors::ParseError,
+    },
+    Other {
+        url: Url,
+        source: anyhow::Error,
+    },
+}
+
+impl FetchError {
+    // For `map_err` on a write of `path` while saving `url`
+    pub fn io(url: &Url, path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let url = url.clone();
+        let path = path.into();
+        move |source| Self::Io { url, path, source }
+    }
+
+    // A copy for another task that shares this outcome. io and anyhow errors
+    // can't be cloned, their copies keep the kind and the message
+    pub fn duplicate(&self) -> Self {
+        match self {
+            Self::Http { url, status } => Self::Http {
+                url: url.clone(),
+                status: *status,
+            },
+            Self::Io { url, path, source } => Self::Io {
+                url: url.clone(),
+                path: path.clone(),
+                source: io::Error::new(source.kind(), source.to_string()),
+            },
+            Self::Parse { url, source } => Self::Parse {
+                url: url.clone(),
+                source: source.clone(),
+            },
+            Self::Other { url, source } => Self::Other {
+                url: url.clone(),
+                source: anyhow::anyhow!("{:#}", source),
+            },
+        }
+    }
+
+    // Recovers the FetchError raised somewhere down the task, if there was one
+    pub fn from_anyhow(url: Url, e: anyhow::Error) -> Self {
+        e.downcast()
+            .unwrap_or_else(|source| Self::Other { url, source })
+    }
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http { url, status } => {
+                write!(f, "Error while fetching {} : code {:?}", url, status)
+            }
+            Self::Io { url, path, source } => {
+                write!(f, "Error while saving {} to {:?} : {}", url, path, source)
+            }
+            Self::Parse { url, source } => write!(f, "Error while parsing {} : {}", url, source),
+            Self::Other { url, source } => write!(f, "Error while fetching {} : {}", url, source),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Http { .. } => None,
+            Self::Io { source, .. } => Some(source),
+            Self::Parse { source, .. } => Some(source),
+            Self::Other { source, .. } => Some(source.as_ref()),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use tl::{HTMLTag, HTMLVersion, Node, Parser, VDom};
+
+// whitespace inside these is content, they are written as parsed
+const PREFORMATTED: &[&str] = &["pre", "textarea", "script", "style"];
+// elements that have no content and no end tag
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+// elements that start on a line of their own, whitespace around them doesn't
+// render. Everything else is inline, where a space between two elements does
+const BLOCK: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hgroup",
+    "hr",
+    "html",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+const INDENT: &str = "  ";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout {
+    // block elements on lines of their own, indented by depth
+    Pretty,
+    // whitespace collapsed to single spaces, and no comments
+    Minify,
+}
+
+// Serializes a document with whitespace laid out as asked, instead of as parsed
+pub fn serialize(dom: &VDom, layout: Layout) -> String {
+    let parser = dom.parser();
+    let mut writer = Writer {
+        parser,
+        layout,
+        out: String::new(),
+        at_boundary: true,
+    };
+    // tl drops the doctype, browsers go into quirks mode without it
+    if dom.version() == Some(HTMLVersion::HTML5) {
+        writer.out.push_str("<!DOCTYPE html>");
+        writer.end_block();
+    }
+    for handle in dom.children() {
+        if let Some(node) = handle.get(parser) {
+            writer.write_node(node, 0, false);
+        }
+    }
+    writer.out
+}
+
+struct Writer<'a> {
+    parser: &'a Parser<'a>,
+    layout: Layout,
+    out: String,
+    // right after the start or end of a block, where whitespace doesn't render
+    at_boundary: bool,
+}
+
+impl Writer<'_> {
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    // whitespace next to the edge of a block doesn't render, so the space
+    // before one can go, and Pretty can break the line there instead
+    fn start_block(&mut self, depth: usize) {
+        if self.out.ends_with(' ') {
+            self.out.pop();
+        }
+        if self.layout == Layout::Pretty {
+            if !self.at_line_start() {
+                self.out.push('\n');
+            }
+            self.out.push_str(&INDENT.repeat(depth));
+        }
+    }
+
+    fn end_block(&mut self) {
+        if self.layout == Layout::Pretty {
+            self.out.push('\n');
+        }
+        self.at_boundary = true;
+    }
+
+    // inline content goes on the current line, and is only indented when it starts one
+    fn start_inline(&mut self, depth: usize) {
+        if self.layout == Layout::Pretty && self.at_line_start() {
+            self.out.push_str(&INDENT.repeat(depth));
+        }
+        self.at_boundary = false;
+    }
+
+    fn write_node(&mut self, node: &Node, depth: usize, in_head: bool) {
+        match node {
+            Node::Raw(text) => {
+                // any run of whitespace renders as one space, so keep one
+                let raw = text.as_utf8_str();
+                let words = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+                let mut text = String::new();
+                if raw.starts_with(char::is_whitespace) && !self.at_boundary {
+                    text.push(' ');
+                }
+                if !words.is_empty() {
+                    text.push_str(&words);
+                    if raw.ends_with(char::is_whitespace) {
+                        text.push(' ');
+                    }
+                }
+                if text.is_empty() {
+                    return;
+                }
+                self.start_inline(depth);
+                self.out.push_str(&text);
+            }
+            Node::Comment(comment) => {
+                if self.layout == Layout::Pretty {
+                    let at_boundary = self.at_boundary;
+                    self.start_inline(depth);
+                    self.out.push_str(&comment.as_utf8_str());
+                    // a comment doesn't render, so it doesn't end a boundary either
+                    self.at_boundary = at_boundary;
+                }
+            }
+            Node::Tag(tag) => {
+                let name = tag.name().as_utf8_str().to_ascii_lowercase();
+                // nothing in <head> renders, so its children can go on lines of their own
+                let block = in_head || BLOCK.contains(&name.as_str());
+                if block {
+                    self.start_block(depth);
+                } else {
+                    self.start_inline(depth);
+                }
+                write_open_tag(tag, &mut self.out);
+                if VOID.contains(&name.as_str()) {
+                    if block {
+                        self.end_block();
+                    }
+                    return;
+                }
+                if PREFORMATTED.contains(&name.as_str()) {
+                    for handle in tag.children().top().iter() {
+                        if let Some(child) = handle.get(self.parser) {
+                            self.out.push_str(&child.inner_html(self.parser));
+                        }
+                    }
+                } else {
+                    if block {
+                        self.end_block();
+                    }
+                    for handle in tag.children().top().iter() {
+                        if let Some(child) = handle.get(self.parser) {
+                            self.write_node(child, depth + 1, name == "head");
+                        }
+                    }
+                    if block {
+                        self.start_block(depth);
+                    }
+                }
+                self.out.push_str("</");
+                self.out.push_str(&tag.name().as_utf8_str());
+                self.out.push('>');
+                if block {
+                    self.end_block();
+                } else {
+                    self.at_boundary = false;
+                }
+            }
+        }
+    }
+}
+
+fn write_open_tag(tag: &HTMLTag, out: &mut String) {
+    out.push('<');
+    out.push_str(&tag.name().as_utf8_str());
+    for (key, value) in tag.attributes().iter() {
+        out.push(' ');
+        out.push_str(&key);
+        if let Some(value) = value {
+            // values are kept as written, so pick a quote they don't contain
+            let quote = if value.contains('"') { '\'' } else { '"' };
+            out.push('=');
+            out.push(quote);
+            out.push_str(&value);
+            out.push(quote);
+        }
+    }
+    out.push('>');
+}
+
+#[cfg(test)]
+mod tests {
+    use tl::{parse, ParserOptions};
+
+    use super::*;
+
+    fn layout(html: &str, layout: Layout) -> String {
+        let dom = parse(html, ParserOptions::default()).unwrap();
+        serialize(&dom, layout)
+    }
+
+    #[test]
+    fn pretty_puts_blocks_on_lines_of_their_own() {
+        let html = "<html><head><title>t</title><meta charset=\"utf-8\"></head>\
+                    <body><div><p>a <b>b</b></p>\n\n  <p>c</p></div></body></html>";
+        assert_eq!(
+            layout(html, Layout::Pretty),
+            "<html>\n  <head>\n    <title>\n      t\n    </title>\n    <meta charset=\"utf-8\">\n  \
+             </head>\n  <body>\n    <div>\n      <p>\n        a <b>b</b>\n      </p>\n      \
+             <p>\n        c\n      </p>\n    </div>\n  </body>\n</html>\n"
+        );
+    }
+
+    #[test]
+    fn inline_elements_keep_their_spacing() {
+        let spaced = "<p><b>x</b> <i>y</i>\n<a href=\"/\">z</a></p>";
+        let tight = "<p><b>x</b><i>y</i></p>";
+        assert_eq!(
+            layout(spaced, Layout::Minify),
+            "<p><b>x</b> <i>y</i> <a href=\"/\">z</a></p>"
+        );
+        assert_eq!(layout(tight, Layout::Minify), tight);
+        assert!(layout(spaced, Layout::Pretty).contains("<b>x</b> <i>y</i> <a href=\"/\">z</a>"));
+        // pretty must not add a space that renders between them
+        assert!(layout(tight, Layout::Pretty).contains("<b>x</b><i>y</i>"));
+    }
+
+    #[test]
+    fn whitespace_between_blocks_is_dropped_when_minified() {
+        assert_eq!(
+            layout("<div>\n  <p> a </p>\n  <p>b</p>\n</div>", Layout::Minify),
+            "<div><p>a</p><p>b</p></div>"
+        );
+    }
+
+    #[test]
+    fn preformatted_content_is_kept_as_is() {
+        let html = "<div><pre>  a\n    b  </pre><textarea> x\n\n y </textarea></div>";
+        for l in [Layout::Pretty, Layout::Minify] {
+            let out = layout(html, l);
+            assert!(out.contains("<pre>  a\n    b  </pre>"), "{:?}: {}", l, out);
+            assert!(
+                out.contains("<textarea> x\n\n y </textarea>"),
+                "{:?}: {}",
+                l,
+                out
+            );
+        }
+    }
+
+    #[test]
+    fn comments_are_stripped_when_minified() {
+        let html = "<div><!-- note -->a<!-- inline -->b</div>";
+        assert_eq!(layout(html, Layout::Minify), "<div>ab</div>");
+        let pretty = layout(html, Layout::Pretty);
+        assert!(
+            pretty.contains("<!-- note -->a<!-- inline -->b"),
+            "{}",
+            pretty
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use std::{io::ErrorKind, path::Path};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::output::write_atomic;
+
+// A task that was queued but hadn't finished when the frontier was saved
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PendingTask {
+    pub url: String,
+    pub page: bool,
+    pub form: Option<String>,
+    pub depth: usize,
+}
+
+// What an interrupted crawl needs to pick up where it stopped
+#[derive(Default, Serialize, Deserialize)]
+pub struct Frontier {
+    pub visited: Vec<String>,
+    pub pending: Vec<PendingTask>,
+}
+
+pub async fn load(path: &Path) -> Result<Option<Frontier>> {
+    let json = match fs::read(path).await {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let frontier =
+        serde_json::from_slice(&json).with_context(|| format!("invalid frontier {:?}", path))?;
+    Ok(Some(frontier))
+}
+
+pub async fn save(path: &Path, frontier: &Frontier) -> Result<()> {
+    write_atomic(path, serde_json::to_vec(frontier)?).await
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Write,
+};
+
+use reqwest::Url;
+
+// host and path of `url`, without the scheme
+fn short_name(url: &Url) -> String {
+    let mut name = format!("{}{}", url.host_str().unwrap_or_default(), url.path());
+    if let Some(query) = url.query() {
+        name.push('?');
+        name.push_str(query);
+    }
+    name
+}
+
+// parent -> child edges discovered during a run, in discovery order
+#[derive(Default)]
+pub struct LinkGraph {
+    roots: Vec<Url>,
+    edges: HashSet<(Url, Url)>,
+    // parents in the order their first edge was added
+    parents: Vec<Url>,
+    children: HashMap<Url, Vec<Url>>,
+}
+
+impl LinkGraph {
+    pub fn add_root(&mut self, url: Url) {
+        self.roots.push(url);
+    }
+
+    pub fn add_edge(&mut self, parent: Url, child: Url) {
+        if !self.edges.insert((parent.clone(), child.clone())) {
+            return;
+        }
+        let children = self.children.entry(parent.clone()).or_insert_with(|| {
+            self.parents.push(parent);
+            vec![]
+        });
+        children.push(child);
+    }
+
+    fn children<'a>(&'a self, parent: &Url) -> impl Iterator<Item = &'a Url> + 'a {
+        self.children.get(parent).into_iter().flatten()
+    }
+
+    // every edge, grouped by parent
+    fn edges(&self) -> impl Iterator<Item = (&Url, &Url)> + '_ {
+        self.parents
+            .iter()
+            .flat_map(move |parent| self.children(parent).map(move |child| (parent, child)))
+    }
+
+    // every node is expanded once, under the parent that reaches it first
+    // breadth-first, other occurrences are marked as already seen
+    fn tree_parents(&self) -> HashMap<&Url, Option<&Url>> {
+        let mut owners = HashMap::new();
+        let mut queue = VecDeque::new();
+        for root in &self.roots {
+            if !owners.contains_key(root) {
+                owners.insert(root, None);
+                queue.push_back(root);
+            }
+        }
+        while let Some(url) = queue.pop_front() {
+            for child in self.children(url) {
+                if !owners.contains_key(child) {
+                    owners.insert(child, Some(url));
+                    queue.push_back(child);
+                }
+            }
+        }
+        owners
+    }
+
+    pub fn render_tree(&self) -> String {
+        let owners = self.tree_parents();
+        let mut out = String::new();
+        let mut roots = HashSet::new();
+        for root in &self.roots {
+            let expand = roots.insert(root);
+            self.render_node(root, None, expand, "", "", &owners, &mut out);
+        }
+        out
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_node(
+        &self,
+        url: &Url,
+        parent: Option<&Url>,
+        expand: bool,
+        prefix: &str,
+        child_prefix: &str,
+        owners: &HashMap<&Url, Option<&Url>>,
+        out: &mut String,
+    ) {
+        if !expand || owners.get(url) != Some(&parent) {
+            writeln!(out, "{}{} (seen)", prefix, url).unwrap();
+            return;
+        }
+        writeln!(out, "{}{}", prefix, url).unwrap();
+        let children: Vec<_> = self.children(url).collect();
+        for (i, child) in children.iter().enumerate() {
+            let (branch, next) = if i + 1 == children.len() {
+                ("`-- ", "    ")
+            } else {
+                ("|-- ", "|   ")
+            };
+            self.render_node(
+                child,
+                Some(url),
+                true,
+                &format!("{}{}", child_prefix, branch),
+                &format!("{}{}", child_prefix, next),
+                owners,
+                out,
+            );
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph crawl {\n");
+        let mut nodes = HashSet::new();
+        let roots: HashSet<_> = self.roots.iter().collect();
+        let all = self
+            .roots
+            .iter()
+            .chain(self.edges().flat_map(|(p, c)| [p, c]));
+        for url in all {
+            if nodes.insert(url) {
+                let shape = if roots.contains(url) {
+                    "box"
+                } else {
+                    "ellipse"
+                };
+                writeln!(
+                    out,
+                    "    {:?} [label={:?}, shape={}];",
+                    url.as_str(),
+                    short_name(url),
+                    shape
+                )
+                .unwrap();
+            }
+        }
+        for (parent, child) in self.edges() {
+            writeln!(out, "    {:?} -> {:?};", parent.as_str(), child.as_str()).unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(path: &str) -> Url {
+        Url::parse(&format!("https://example.com/{}", path)).unwrap()
+    }
+
+    // a -> b -> c -> a, plus a -> c and a link from a to itself
+    fn cyclic() -> LinkGraph {
+        let mut graph = LinkGraph::default();
+        graph.add_root(url("a"));
+        graph.add_edge(url("a"), url("b"));
+        graph.add_edge(url("b"), url("c"));
+        graph.add_edge(url("c"), url("a"));
+        graph.add_edge(url("a"), url("c"));
+        graph.add_edge(url("a"), url("a"));
+        // duplicates are ignored
+        graph.add_edge(url("a"), url("b"));
+        graph
+    }
+
+    #[test]
+    fn tree_marks_cycles_as_seen() {
+        assert_eq!(
+            cyclic().render_tree(),
+            "https://example.com/a\n\
+             |-- https://example.com/b\n\
+             |   `-- https://example.com/c (seen)\n\
+             |-- https://example.com/c\n\
+             |   `-- https://example.com/a (seen)\n\
+             `-- https://example.com/a (seen)\n"
+        );
+    }
+
+    #[test]
+    fn repeated_roots_are_only_expanded_once() {
+        let mut graph = cyclic();
+        graph.add_root(url("a"));
+        let tree = graph.render_tree();
+        assert!(
+            tree.ends_with("\nhttps://example.com/a (seen)\n"),
+            "{}",
+            tree
+        );
+    }
+
+    #[test]
+    fn large_graphs_render() {
+        let mut graph = LinkGraph::default();
+        graph.add_root(url("0"));
+        for i in 0..20_000 {
+            graph.add_edge(url(&(i / 10).to_string()), url(&i.to_string()));
+            graph.add_edge(url(&(i / 10).to_string()), url(&i.to_string()));
+        }
+        assert_eq!(graph.edges().count(), 20_000);
+        // every node once, and 0 linking to itself
+        assert_eq!(graph.render_tree().lines().count(), 20_001);
+    }
+
+    #[test]
+    fn dot_lists_each_node_once_and_every_edge() {
+        let dot = cyclic().to_dot();
+        assert!(dot.starts_with("digraph crawl {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert_eq!(dot.matches("[label=").count(), 3, "{}", dot);
+        assert_eq!(dot.matches(" -> ").count(), 5, "{}", dot);
+        assert!(dot.contains("\"https://example.com/a\" [label=\"example.com/a\", shape=box];"));
+        assert!(dot.contains("\"https://example.com/c\" [label=\"example.com/c\", shape=ellipse];"));
+        assert!(dot.contains("\"https://example.com/c\" -> \"https://example.com/a\";"));
+    }
+}
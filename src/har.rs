@@ -0,0 +1,303 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use reqwest::{header::HeaderMap, Request, Response};
+use serde::Serialize;
+
+use crate::http;
+
+// Just enough of HAR 1.2 (http://www.softwareishard.com/blog/har-12-spec/)
+// for the requests rget makes; sizes we don't know are -1 as the spec asks
+
+#[derive(Serialize)]
+struct Header {
+    name: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    method: String,
+    url: String,
+    http_version: String,
+    cookies: Vec<Header>,
+    headers: Vec<Header>,
+    query_string: Vec<Header>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Content {
+    size: i64,
+    mime_type: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HarResponse {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    cookies: Vec<Header>,
+    headers: Vec<Header>,
+    content: Content,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Serialize)]
+struct Cache {}
+
+#[derive(Serialize)]
+struct Timings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    started_date_time: String,
+    time: f64,
+    request: HarRequest,
+    response: HarResponse,
+    cache: Cache,
+    timings: Timings,
+}
+
+fn headers(map: &HeaderMap) -> Vec<Header> {
+    map.iter()
+        .map(|(name, value)| Header {
+            name: name.to_string(),
+            value: http::shown_value(name, value).to_owned(),
+        })
+        .collect()
+}
+
+// The headers the client sends: a header the request sets replaces the
+// default one of the same name, as reqwest does
+fn sent_headers(default_headers: &HeaderMap, request_headers: &HeaderMap) -> HeaderMap {
+    let mut sent = default_headers.clone();
+    for name in request_headers.keys() {
+        sent.remove(name);
+    }
+    for (name, value) in request_headers {
+        sent.append(name, value.clone());
+    }
+    sent
+}
+
+// What we're about to send, taken before the request is consumed
+pub fn request(req: &Request, default_headers: &HeaderMap) -> HarRequest {
+    HarRequest {
+        method: req.method().to_string(),
+        url: req.url().to_string(),
+        http_version: http::version_name(req.version()).to_owned(),
+        cookies: vec![],
+        headers: headers(&sent_headers(default_headers, req.headers())),
+        query_string: req
+            .url()
+            .query_pairs()
+            .map(|(name, value)| Header {
+                name: name.into_owned(),
+                value: value.into_owned(),
+            })
+            .collect(),
+        headers_size: -1,
+        body_size: req
+            .body()
+            .and_then(|b| b.as_bytes())
+            .map_or(0, |b| b.len() as i64),
+    }
+}
+
+// `wait` is the time to the response headers, the body isn't timed separately
+pub fn entry(
+    request: HarRequest,
+    resp: &Response,
+    started: DateTime<Local>,
+    wait: Duration,
+) -> Entry {
+    let wait = wait.as_secs_f64() * 1000.0;
+    let size = resp.content_length().map_or(-1, |l| l as i64);
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned()
+    };
+    Entry {
+        started_date_time: started.to_rfc3339(),
+        time: wait,
+        request,
+        response: HarResponse {
+            status: resp.status().as_u16(),
+            status_text: resp
+                .status()
+                .canonical_reason()
+                .unwrap_or_default()
+                .to_owned(),
+            http_version: http::version_name(resp.version()).to_owned(),
+            cookies: vec![],
+            headers: headers(resp.headers()),
+            content: Content {
+                size,
+                mime_type: header(reqwest::header::CONTENT_TYPE),
+            },
+            redirect_url: header(reqwest::header::LOCATION),
+            headers_size: -1,
+            body_size: size,
+        },
+        cache: Cache {},
+        timings: Timings {
+            send: 0.0,
+            wait,
+            receive: 0.0,
+        },
+    }
+}
+
+pub fn to_json(entries: &[Entry]) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&serde_json::json!({
+        "log": {
+            "version": "1.2",
+            "creator": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "entries": entries,
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{
+        header::{HeaderValue, ACCEPT, CONTENT_TYPE, USER_AGENT},
+        Method, Url,
+    };
+
+    use super::*;
+
+    fn get(url: &str, headers: &[(reqwest::header::HeaderName, &'static str)]) -> Request {
+        let mut req = Request::new(Method::GET, Url::parse(url).unwrap());
+        for (name, value) in headers {
+            req.headers_mut()
+                .append(name, HeaderValue::from_static(value));
+        }
+        req
+    }
+
+    fn defaults() -> HeaderMap {
+        let mut defaults = HeaderMap::new();
+        defaults.insert(USER_AGENT, HeaderValue::from_static("rget"));
+        defaults.insert(ACCEPT, HeaderValue::from_static("*/*"));
+        defaults
+    }
+
+    fn names_and_values(request: &HarRequest) -> Vec<(&str, &str)> {
+        request
+            .headers
+            .iter()
+            .map(|h| (h.name.as_str(), h.value.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn request_headers_replace_defaults() {
+        let req = get("https://example.com/", &[(USER_AGENT, "custom")]);
+        let request = request(&req, &defaults());
+        let mut sent = names_and_values(&request);
+        sent.sort();
+        assert_eq!(sent, [("accept", "*/*"), ("user-agent", "custom")]);
+    }
+
+    #[test]
+    fn repeated_request_headers_are_all_kept() {
+        let req = get(
+            "https://example.com/",
+            &[(ACCEPT, "text/html"), (ACCEPT, "application/xml")],
+        );
+        let request = request(&req, &defaults());
+        let accepts: Vec<_> = names_and_values(&request)
+            .into_iter()
+            .filter(|(name, _)| *name == "accept")
+            .map(|(_, value)| value)
+            .collect();
+        assert_eq!(accepts, ["text/html", "application/xml"]);
+    }
+
+    #[test]
+    fn has_the_required_fields() {
+        let req = get("https://example.com/a?q=1", &[]);
+        let resp = Response::from(
+            ::http::Response::builder()
+                .status(200)
+                .header(CONTENT_TYPE, "text/html")
+                .body("<html></html>")
+                .unwrap(),
+        );
+        let entry = entry(
+            request(&req, &defaults()),
+            &resp,
+            Local::now(),
+            Duration::from_millis(5),
+        );
+        let har: serde_json::Value = serde_json::from_str(&to_json(&[entry]).unwrap()).unwrap();
+
+        let log = &har["log"];
+        assert_eq!(log["version"], "1.2");
+        assert!(log["creator"]["name"].is_string());
+        assert!(log["creator"]["version"].is_string());
+        let entry = &log["entries"][0];
+        for field in [
+            "startedDateTime",
+            "time",
+            "request",
+            "response",
+            "cache",
+            "timings",
+        ] {
+            assert!(!entry[field].is_null(), "entry.{}", field);
+        }
+        for field in [
+            "method",
+            "url",
+            "httpVersion",
+            "cookies",
+            "headers",
+            "queryString",
+            "headersSize",
+            "bodySize",
+        ] {
+            assert!(!entry["request"][field].is_null(), "request.{}", field);
+        }
+        for field in [
+            "status",
+            "statusText",
+            "httpVersion",
+            "cookies",
+            "headers",
+            "content",
+            "redirectURL",
+            "headersSize",
+            "bodySize",
+        ] {
+            assert!(!entry["response"][field].is_null(), "response.{}", field);
+        }
+        assert!(entry["response"]["content"]["size"].is_number());
+        assert_eq!(entry["response"]["content"]["mimeType"], "text/html");
+        for field in ["send", "wait", "receive"] {
+            assert!(entry["timings"][field].is_number(), "timings.{}", field);
+        }
+        assert_eq!(entry["request"]["queryString"][0]["name"], "q");
+        assert_eq!(entry["response"]["status"], 200);
+    }
+}
@@ -0,0 +1,100 @@
+// Renders pages in a headless chromium, for sites that build their content
+// with javascript. Only available with the `headless` cargo feature.
+
+#[cfg(feature = "headless")]
+mod imp {
+    use std::time::Duration;
+
+    use anyhow::{anyhow, Context, Result};
+    use chromiumoxide::{Browser, BrowserConfig};
+    use futures::StreamExt;
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, CONTENT_TYPE},
+        Response, StatusCode, Url, Version,
+    };
+    use tokio::{sync::OnceCell, time::Instant};
+    use tracing::{debug, info};
+
+    // how long a page gets to show the selector it is waited on for
+    const WAIT_TIMEOUT: Duration = Duration::from_secs(30);
+    const WAIT_POLL: Duration = Duration::from_millis(100);
+
+    // One browser for the whole run, started by the first page that needs it
+    #[derive(Default)]
+    pub struct Headless {
+        browser: OnceCell<Browser>,
+    }
+
+    async fn launch() -> Result<Browser> {
+        let config = BrowserConfig::builder()
+            .build()
+            .map_err(|e| anyhow!("can't configure chromium : {}", e))?;
+        let (browser, mut handler) = Browser::launch(config)
+            .await
+            .context("can't start chromium")?;
+        // the browser only makes progress while its handler is polled
+        tokio::spawn(async move {
+            while let Some(event) = handler.next().await {
+                if let Err(e) = event {
+                    debug!("chromium handler stopped : {}", e);
+                    break;
+                }
+            }
+        });
+        info!("started headless chromium");
+        Ok(browser)
+    }
+
+    impl Headless {
+        pub async fn render(&self, url: &Url, selector: Option<&str>) -> Result<Response> {
+            let browser = self.browser.get_or_try_init(launch).await?;
+            let page = browser.new_page(url.as_str()).await?;
+            page.wait_for_navigation().await?;
+            if let Some(selector) = selector {
+                let deadline = Instant::now() + WAIT_TIMEOUT;
+                while page.find_element(selector).await.is_err() {
+                    if Instant::now() >= deadline {
+                        return Err(anyhow!(
+                            "{} didn't show {:?} within {:?}",
+                            url,
+                            selector,
+                            WAIT_TIMEOUT
+                        ));
+                    }
+                    tokio::time::sleep(WAIT_POLL).await;
+                }
+            }
+            let html = page.content().await?;
+            page.close().await?;
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            Ok(crate::http::rebuild(
+                url,
+                StatusCode::OK,
+                Version::HTTP_11,
+                headers,
+                html.into_bytes(),
+            ))
+        }
+    }
+}
+
+#[cfg(not(feature = "headless"))]
+mod imp {
+    use anyhow::{anyhow, Result};
+    use reqwest::{Response, Url};
+
+    #[derive(Default)]
+    pub struct Headless;
+
+    impl Headless {
+        pub async fn render(&self, _url: &Url, _selector: Option<&str>) -> Result<Response> {
+            Err(anyhow!("rget was built without headless support"))
+        }
+    }
+}
+
+pub use imp::Headless;
@@ -0,0 +1,359 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use reqwest::Url;
+use tokio::{
+    sync::Notify,
+    time::{sleep, sleep_until, Instant},
+};
+use tracing::{info, warn};
+
+// number of recent requests the error rate is computed over
+const ERROR_WINDOW: usize = 20;
+// don't trip the breaker before we have seen this many requests
+const MIN_SAMPLES: usize = 5;
+const HALF_OPEN_POLL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Circuit {
+    Closed,
+    Open(Instant),
+    HalfOpen,
+}
+
+// Whether `url` is on `seed`'s host, or with `subdomains` on any host of the
+// same registrable domain, `blog.example.co.uk` for `www.example.co.uk`
+pub fn same_site(url: &Url, seed: &Url, subdomains: bool) -> bool {
+    if url.host_str() == seed.host_str() {
+        return true;
+    }
+    // ip addresses have no subdomains
+    match (url.domain(), seed.domain()) {
+        (Some(host), Some(seed)) if subdomains => {
+            psl::domain_str(host).is_some_and(|domain| psl::domain_str(seed) == Some(domain))
+        }
+        _ => false,
+    }
+}
+
+struct HostState {
+    circuit: Circuit,
+    outcomes: VecDeque<bool>,
+    // tasks currently holding a slot for this host
+    active: usize,
+    // time tasks have spent fetching from this host
+    spent: Duration,
+    // earliest time the next request may go out, with a crawl delay
+    next_request: Option<Instant>,
+}
+
+impl Default for HostState {
+    fn default() -> Self {
+        Self {
+            circuit: Circuit::Closed,
+            outcomes: VecDeque::with_capacity(ERROR_WINDOW),
+            active: 0,
+            spent: Duration::ZERO,
+            next_request: None,
+        }
+    }
+}
+
+impl HostState {
+    fn error_rate(&self) -> f64 {
+        let errors = self.outcomes.iter().filter(|ok| !**ok).count();
+        errors as f64 / self.outcomes.len() as f64
+    }
+}
+
+pub struct BreakerConfig {
+    pub threshold: f64,
+    pub cooldown: Duration,
+}
+
+// Per-host state shared by all tasks of a run
+#[derive(Default)]
+pub struct Hosts {
+    hosts: Mutex<HashMap<String, HostState>>,
+    slot_freed: Notify,
+}
+
+// Keeps a host counted as active until dropped
+pub struct HostSlot<'a> {
+    hosts: &'a Hosts,
+    host: String,
+}
+
+impl Drop for HostSlot<'_> {
+    fn drop(&mut self) {
+        let freed = {
+            let mut hosts = self.hosts.hosts.lock().unwrap();
+            let state = hosts.entry(self.host.clone()).or_default();
+            state.active -= 1;
+            state.active == 0
+        };
+        if freed {
+            self.hosts.slot_freed.notify_waiters();
+        }
+    }
+}
+
+// Adds the time a task spent on a host to the host's total when dropped
+pub struct HostTimer<'a> {
+    hosts: &'a Hosts,
+    host: String,
+    budget: Duration,
+    started: Instant,
+}
+
+impl Drop for HostTimer<'_> {
+    fn drop(&mut self) {
+        let mut hosts = self.hosts.hosts.lock().unwrap();
+        let state = hosts.entry(self.host.clone()).or_default();
+        let was_within = state.spent < self.budget;
+        state.spent += self.started.elapsed();
+        if was_within && state.spent >= self.budget {
+            warn!(
+                "abandoning {} after spending {:?} on it",
+                self.host, state.spent
+            );
+        }
+    }
+}
+
+impl Hosts {
+    // Starts timing a task on `host`, unless the host already used up `budget`
+    pub fn start_timer(&self, host: &str, budget: Duration) -> Option<HostTimer<'_>> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_owned()).or_default();
+        (state.spent < budget).then(|| HostTimer {
+            hosts: self,
+            host: host.to_owned(),
+            budget,
+            started: Instant::now(),
+        })
+    }
+
+    // Waits until `host` is one of at most `limit` hosts being crawled at once
+    pub async fn acquire_slot(&self, host: &str, limit: usize) -> HostSlot<'_> {
+        let mut deferred = false;
+        loop {
+            let notified = {
+                let mut hosts = self.hosts.lock().unwrap();
+                let busy = hosts.values().filter(|s| s.active > 0).count();
+                let state = hosts.entry(host.to_owned()).or_default();
+                if state.active > 0 || busy < limit {
+                    state.active += 1;
+                    return HostSlot {
+                        hosts: self,
+                        host: host.to_owned(),
+                    };
+                }
+                // registered while locked, so a slot freed after this wakes us
+                self.slot_freed.notified()
+            };
+            if !deferred {
+                info!("deferring {}, {} hosts already active", host, limit);
+                deferred = true;
+            }
+            notified.await;
+        }
+    }
+
+    // Waits for the turn of a request to `host`, `delay` after the previous one
+    pub async fn wait_turn(&self, host: &str, delay: Duration) {
+        let at = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let state = hosts.entry(host.to_owned()).or_default();
+            let at = state
+                .next_request
+                .map_or(Instant::now(), |at| at.max(Instant::now()));
+            // reserved now so that concurrent tasks queue up behind each other
+            state.next_request = Some(at + delay);
+            at
+        };
+        sleep_until(at).await;
+    }
+
+    // Waits until the circuit for `host` lets a request through
+    pub async fn wait_for_circuit(&self, host: &str) {
+        loop {
+            let wait = {
+                let mut hosts = self.hosts.lock().unwrap();
+                let state = hosts.entry(host.to_owned()).or_default();
+                match state.circuit {
+                    Circuit::Closed => return,
+                    Circuit::Open(until) if Instant::now() >= until => {
+                        info!("circuit for {} half-open, probing", host);
+                        state.circuit = Circuit::HalfOpen;
+                        return;
+                    }
+                    Circuit::Open(until) => until - Instant::now(),
+                    Circuit::HalfOpen => HALF_OPEN_POLL,
+                }
+            };
+            sleep(wait).await;
+        }
+    }
+
+    pub fn record_outcome(&self, host: &str, ok: bool, breaker: &BreakerConfig) {
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = hosts.entry(host.to_owned()).or_default();
+        match state.circuit {
+            Circuit::HalfOpen if ok => {
+                info!("circuit for {} closed", host);
+                state.circuit = Circuit::Closed;
+                state.outcomes.clear();
+            }
+            Circuit::HalfOpen => {
+                warn!("circuit for {} re-opened for {:?}", host, breaker.cooldown);
+                state.circuit = Circuit::Open(Instant::now() + breaker.cooldown);
+            }
+            Circuit::Closed => {
+                if state.outcomes.len() == ERROR_WINDOW {
+                    state.outcomes.pop_front();
+                }
+                state.outcomes.push_back(ok);
+                if state.outcomes.len() >= MIN_SAMPLES && state.error_rate() > breaker.threshold {
+                    warn!(
+                        "circuit for {} opened for {:?}, error rate {:.0}%",
+                        host,
+                        breaker.cooldown,
+                        state.error_rate() * 100.0
+                    );
+                    state.circuit = Circuit::Open(Instant::now() + breaker.cooldown);
+                }
+            }
+            // late results of requests sent before the circuit opened
+            Circuit::Open(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BREAKER: BreakerConfig = BreakerConfig {
+        threshold: 0.5,
+        cooldown: Duration::from_millis(50),
+    };
+
+    fn circuit(hosts: &Hosts) -> Circuit {
+        hosts.hosts.lock().unwrap()["example.com"].circuit
+    }
+
+    fn record(hosts: &Hosts, outcomes: &[bool]) {
+        for ok in outcomes {
+            hosts.record_outcome("example.com", *ok, &BREAKER);
+        }
+    }
+
+    #[test]
+    fn same_site_by_host_or_registrable_domain() {
+        let url = |u| Url::parse(u).unwrap();
+        let same = |a, b, subdomains| same_site(&url(a), &url(b), subdomains);
+
+        assert!(same("https://example.com/a", "http://example.com/", false));
+        assert!(!same(
+            "https://www.example.com/",
+            "https://example.com/",
+            false
+        ));
+        assert!(same(
+            "https://www.example.com/",
+            "https://example.com/",
+            true
+        ));
+        assert!(same(
+            "https://a.example.co.uk/",
+            "https://b.example.co.uk/",
+            true
+        ));
+        assert!(!same(
+            "https://a.example.co.uk/",
+            "https://b.example.co.uk/",
+            false
+        ));
+        // sites that only share a public suffix
+        assert!(!same(
+            "https://other.co.uk/",
+            "https://example.co.uk/",
+            true
+        ));
+        assert!(!same(
+            "https://evil-example.com/",
+            "https://example.com/",
+            true
+        ));
+        assert!(!same(
+            "https://example.com.evil.com/",
+            "https://example.com/",
+            true
+        ));
+        // ip addresses only match themselves
+        assert!(same("http://127.0.0.1:8080/", "http://127.0.0.1/", true));
+        assert!(!same("http://127.0.0.2/", "http://127.0.0.1/", true));
+    }
+
+    #[test]
+    fn opens_above_the_threshold() {
+        let hosts = Hosts::default();
+        // half the requests failing isn't above the threshold
+        record(&hosts, &[true, false, true, false, true, false]);
+        assert_eq!(circuit(&hosts), Circuit::Closed);
+        record(&hosts, &[false]);
+        assert!(matches!(circuit(&hosts), Circuit::Open(_)));
+    }
+
+    #[test]
+    fn needs_enough_samples_to_open() {
+        let hosts = Hosts::default();
+        record(&hosts, &[false; MIN_SAMPLES - 1]);
+        assert_eq!(circuit(&hosts), Circuit::Closed);
+        record(&hosts, &[false]);
+        assert!(matches!(circuit(&hosts), Circuit::Open(_)));
+    }
+
+    #[test]
+    fn only_recent_requests_count() {
+        let hosts = Hosts::default();
+        record(&hosts, &[true; 100]);
+        record(&hosts, &[false; ERROR_WINDOW / 2]);
+        // half of the last 20, however many succeeded before them
+        assert_eq!(circuit(&hosts), Circuit::Closed);
+        record(&hosts, &[false]);
+        assert!(matches!(circuit(&hosts), Circuit::Open(_)));
+    }
+
+    #[tokio::test]
+    async fn closes_after_a_successful_probe() {
+        let hosts = Hosts::default();
+        record(&hosts, &[false; MIN_SAMPLES]);
+        assert!(matches!(circuit(&hosts), Circuit::Open(_)));
+
+        let started = Instant::now();
+        hosts.wait_for_circuit("example.com").await;
+        assert!(started.elapsed() >= BREAKER.cooldown);
+        assert_eq!(circuit(&hosts), Circuit::HalfOpen);
+
+        record(&hosts, &[true]);
+        assert_eq!(circuit(&hosts), Circuit::Closed);
+        // the errors from before are forgotten
+        record(&hosts, &[false]);
+        assert_eq!(circuit(&hosts), Circuit::Closed);
+    }
+
+    #[tokio::test]
+    async fn reopens_after_a_failed_probe() {
+        let hosts = Hosts::default();
+        record(&hosts, &[false; MIN_SAMPLES]);
+        hosts.wait_for_circuit("example.com").await;
+        assert_eq!(circuit(&hosts), Circuit::HalfOpen);
+        record(&hosts, &[false]);
+        assert!(matches!(circuit(&hosts), Circuit::Open(_)));
+    }
+}
@@ -0,0 +1,757 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::{
+    header::{
+        HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, COOKIE,
+        PROXY_AUTHORIZATION, SET_COOKIE,
+    },
+    redirect::Policy,
+    Response, ResponseBuilderExt, StatusCode, Url, Version,
+};
+use tracing::{debug, info, trace, warn};
+
+// never written out by --http-trace or --har-output
+const REDACTED: &[HeaderName] = &[AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE];
+
+// same as reqwest's default policy
+const MAX_REDIRECTS: usize = 10;
+
+// Follows redirects like reqwest does by default, but only to the host the
+// request was first sent to, so another host's content can't end up saved
+// under the original url's name.
+pub fn same_host_redirects() -> Policy {
+    Policy::custom(|attempt| {
+        let origin = attempt.previous()[0].host_str();
+        if attempt.url().host_str() != origin {
+            warn!(
+                "refusing redirect of {} to another host: {}",
+                attempt.previous()[0],
+                attempt.url()
+            );
+            let error = format!("redirected to another host: {}", attempt.url());
+            attempt.error(error)
+        } else if attempt.previous().len() > MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+// Switches http urls of `hosts`, or of their subdomains, to https
+pub fn upgrade_to_https(url: &mut Url, hosts: &[String]) {
+    if url.scheme() != "http" {
+        return;
+    }
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let listed = hosts.iter().any(|h| {
+        host == *h
+            || host
+                .strip_suffix(h.as_str())
+                .is_some_and(|sub| sub.ends_with('.'))
+    });
+    if listed {
+        let http = url.to_string();
+        // only fails for special schemes switching to non-special ones
+        url.set_scheme("https").ok();
+        debug!("upgrading {} to {}", http, url);
+    }
+}
+
+// Parses an `ext=mime/type` override for --content-type-for
+pub fn parse_type_override(spec: &str) -> Result<(String, String)> {
+    let (ext, mime) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow!("content-type override {:?} is not in ext=mime form", spec))?;
+    let ext = ext.trim().trim_start_matches('.').to_ascii_lowercase();
+    if ext.is_empty() || mime.trim().is_empty() {
+        return Err(anyhow!("content-type override {:?} is incomplete", spec));
+    }
+    Ok((ext, mime.trim().to_owned()))
+}
+
+// The overridden content-type for the extension of the url's last segment
+pub fn type_override<'a>(url: &Url, overrides: &'a HashMap<String, String>) -> Option<&'a str> {
+    let name = url.path_segments()?.next_back()?;
+    let (_, ext) = name.rsplit_once('.')?;
+    overrides.get(&ext.to_ascii_lowercase()).map(String::as_str)
+}
+
+// Parses a `Name=ENV_VAR` mapping and reads the header value from the
+// environment. The value is marked sensitive so it never shows up in logs.
+pub fn header_from_env(spec: &str) -> Result<(HeaderName, HeaderValue)> {
+    let (name, var) = spec
+        .split_once('=')
+        .ok_or_else(|| anyhow!("--header-env {:?} is not in Name=ENV_VAR form", spec))?;
+    let value = std::env::var(var).with_context(|| {
+        format!(
+            "environment variable {} for header {} is not set",
+            var, name
+        )
+    })?;
+    let mut value = HeaderValue::from_str(&value)
+        .with_context(|| format!("environment variable {} is not a valid header value", var))?;
+    value.set_sensitive(true);
+    Ok((name.parse()?, value))
+}
+
+// Parses a `Name: Value` header line
+pub fn parse_header(line: &str) -> Result<(HeaderName, HeaderValue)> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| anyhow!("header {:?} is not in `Name: Value` form", line))?;
+    Ok((name.trim().parse()?, value.trim().parse()?))
+}
+
+// Reads headers from a file with one `Name: Value` per line, `#` comments
+pub fn read_header_file(path: &str) -> Result<Vec<(HeaderName, HeaderValue)>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("can't read header file {}", path))?;
+    content
+        .lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|line| parse_header(line).with_context(|| format!("in header file {}", path)))
+        .collect()
+}
+
+// User agents handed out round-robin, one per request
+#[derive(Default)]
+pub struct UserAgents {
+    agents: Vec<HeaderValue>,
+    next: AtomicUsize,
+}
+
+impl UserAgents {
+    // Reads one user agent per line, skipping blanks and `#` comments
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("can't read user agent file {}", path))?;
+        let agents = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                HeaderValue::from_str(line)
+                    .with_context(|| format!("invalid user agent {:?} in {}", line, path))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if agents.is_empty() {
+            return Err(anyhow!("no user agents in {}", path));
+        }
+        Ok(Self {
+            agents,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn next(&self) -> Option<HeaderValue> {
+        if self.agents.is_empty() {
+            return None;
+        }
+        let i = self.next.fetch_add(1, Ordering::Relaxed);
+        Some(self.agents[i % self.agents.len()].clone())
+    }
+}
+
+// Reads a whole body, giving up if the server goes quiet for `stall_timeout`
+// between two chunks; a plain timeout would cut off large but steady downloads
+pub async fn read_body(mut resp: Response, stall_timeout: Option<Duration>) -> Result<Vec<u8>> {
+    let stall_timeout = match stall_timeout {
+        Some(stall_timeout) => stall_timeout,
+        None => return Ok(resp.bytes().await?.to_vec()),
+    };
+    let mut body = Vec::new();
+    loop {
+        match tokio::time::timeout(stall_timeout, resp.chunk()).await {
+            Ok(chunk) => match chunk? {
+                Some(chunk) => body.extend_from_slice(&chunk),
+                None => return Ok(body),
+            },
+            Err(_) => {
+                return Err(anyhow!(
+                    "download stalled, nothing received for {}ms after {} bytes",
+                    stall_timeout.as_millis(),
+                    body.len()
+                ))
+            }
+        }
+    }
+}
+
+// Whether a response with an empty body is suspicious rather than legitimate
+pub fn expects_content(status: StatusCode, headers: &HeaderMap) -> bool {
+    if matches!(
+        status,
+        StatusCode::NO_CONTENT | StatusCode::RESET_CONTENT | StatusCode::NOT_MODIFIED
+    ) {
+        return false;
+    }
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    [
+        "text/html",
+        "application/xhtml",
+        "text/css",
+        "image/",
+        "font/",
+        "application/pdf",
+    ]
+    .iter()
+    .any(|prefix| content_type.starts_with(prefix))
+        || content_type.contains("javascript")
+}
+
+// Puts a body that was already read back into a response, `url` being where
+// it came from, reqwest makes one up otherwise
+pub fn rebuild(
+    url: &Url,
+    status: StatusCode,
+    version: Version,
+    headers: HeaderMap,
+    body: Vec<u8>,
+) -> Response {
+    let mut resp = ::http::Response::builder()
+        .url(url.clone())
+        .body(body)
+        .expect("an empty response builder can't fail");
+    *resp.status_mut() = status;
+    *resp.version_mut() = version;
+    *resp.headers_mut() = headers;
+    resp.into()
+}
+
+// Limits on response headers. hyper already refuses responses with more than
+// 100 headers or ~400KiB of them while parsing, and reqwest doesn't let us
+// lower that, so these are checked once the headers are in.
+pub struct HeaderLimits {
+    pub max_size: Option<usize>,
+    pub max_count: Option<usize>,
+}
+
+impl HeaderLimits {
+    pub fn check(&self, headers: &HeaderMap) -> Result<()> {
+        let count = headers.len();
+        if let Some(max) = self.max_count.filter(|max| count > *max) {
+            return Err(anyhow!("too many response headers ({} > {})", count, max));
+        }
+        // as sent on the wire, `Name: Value\r\n`
+        let size: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum();
+        if let Some(max) = self.max_size.filter(|max| size > *max) {
+            return Err(anyhow!(
+                "response headers too large ({} > {} bytes)",
+                size,
+                max
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn version_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_11 => "HTTP/1.1",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "unknown",
+    }
+}
+
+// A header value as it can be shown in logs and reports
+pub fn shown_value<'a>(name: &HeaderName, value: &'a HeaderValue) -> &'a str {
+    if value.is_sensitive() || REDACTED.contains(name) {
+        "[REDACTED]"
+    } else {
+        value.to_str().unwrap_or("<binary>")
+    }
+}
+
+// Logs headers curl style, `>` for sent and `<` for received
+pub fn trace_headers(direction: char, headers: &HeaderMap) {
+    for (name, value) in headers {
+        trace!("{} {}: {}", direction, name, shown_value(name, value));
+    }
+}
+
+// Undoes a Content-Encoding the client didn't already take care of. Bodies in
+// an encoding we don't know are kept as they came. None if it inflates to
+// more than `limit` bytes, which is found out without inflating any further.
+pub fn decompress_if_needed(
+    bytes: &[u8],
+    content_encoding: &str,
+    limit: Option<usize>,
+) -> Result<Option<Vec<u8>>> {
+    let mut out = vec![];
+    // encodings are listed in the order they were applied
+    let mut data = bytes.to_vec();
+    for encoding in content_encoding.split(',').rev().map(str::trim) {
+        out.clear();
+        let within = match encoding.to_ascii_lowercase().as_str() {
+            "" | "identity" => continue,
+            "gzip" | "x-gzip" => read_capped(
+                flate2::read::MultiGzDecoder::new(&data[..]),
+                limit,
+                &mut out,
+            )?,
+            "deflate" => {
+                // servers disagree on whether deflate means zlib or raw deflate
+                match read_capped(flate2::read::ZlibDecoder::new(&data[..]), limit, &mut out) {
+                    Ok(within) => within,
+                    Err(_) => {
+                        out.clear();
+                        read_capped(
+                            flate2::read::DeflateDecoder::new(&data[..]),
+                            limit,
+                            &mut out,
+                        )?
+                    }
+                }
+            }
+            "br" => read_capped(brotli::Decompressor::new(&data[..], 4096), limit, &mut out)?,
+            other => {
+                warn!(
+                    "unsupported content-encoding {:?}, keeping the body as is",
+                    other
+                );
+                return Ok(Some(bytes.to_vec()));
+            }
+        };
+        if !within {
+            return Ok(None);
+        }
+        std::mem::swap(&mut data, &mut out);
+    }
+    Ok(Some(data))
+}
+
+// Reads at most one byte past `limit`, and tells whether it stayed within it
+fn read_capped(mut reader: impl Read, limit: Option<usize>, out: &mut Vec<u8>) -> io::Result<bool> {
+    match limit {
+        Some(limit) => {
+            reader.take(limit as u64 + 1).read_to_end(out)?;
+            Ok(out.len() <= limit)
+        }
+        None => {
+            reader.read_to_end(out)?;
+            Ok(true)
+        }
+    }
+}
+
+// how far into a page we look for a <meta> charset, same as browsers
+const META_PRESCAN_LEN: usize = 1024;
+
+// Whether a content-type names html, ignoring case, whitespace and parameters
+pub fn is_html(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default();
+    essence.trim().eq_ignore_ascii_case("text/html")
+}
+
+fn charset_param(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim_matches('"').as_bytes()))
+}
+
+// The charset named in a lowercased `<meta ...` tag, if any
+fn tag_charset(tag: &str) -> Option<&str> {
+    let at = tag.find("charset")?;
+    let value = tag[at + "charset".len()..].trim_start().strip_prefix('=')?;
+    let value = value.trim_start().trim_start_matches(['"', '\'']);
+    let end = value
+        .find(|c: char| c == '"' || c == '\'' || c == ';' || c.is_whitespace())
+        .unwrap_or(value.len());
+    Some(&value[..end])
+}
+
+// Finds `<meta charset=..>` or `<meta http-equiv="Content-Type" content="..; charset=..">`
+// near the start of the page
+fn meta_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(META_PRESCAN_LEN)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    head.match_indices("<meta")
+        .map(|(start, _)| {
+            let tag = &head[start..];
+            &tag[..tag.find('>').unwrap_or(tag.len())]
+        })
+        .filter_map(tag_charset)
+        .find_map(|label| Encoding::for_label(label.as_bytes()))
+        // a page that could be read as ascii to find this tag can't be utf-16
+        .map(Encoding::output_encoding)
+}
+
+// how much of a page is fed to the charset detector
+const DETECT_LEN: usize = 64 * 1024;
+
+// What to do with pages that don't declare their charset
+pub struct CharsetFallback {
+    // used instead of detection when set
+    pub assume: Option<&'static Encoding>,
+    // guesses the detector is less sure of than this fall back to utf-8
+    pub min_confidence: f32,
+}
+
+// The charset a page was decoded with, and how sure the detector was if it guessed it
+pub struct Charset {
+    pub encoding: &'static Encoding,
+    pub confidence: Option<f32>,
+}
+
+fn detect_charset(bytes: &[u8], min_confidence: f32) -> Option<(&'static Encoding, f32)> {
+    let (name, confidence, _) = chardet::detect(&bytes[..bytes.len().min(DETECT_LEN)]);
+    let encoding = Encoding::for_label(chardet::charset2encoding(&name).as_bytes())?;
+    if confidence < min_confidence {
+        debug!(
+            "ignoring detected charset {} with confidence {:.2}",
+            encoding.name(),
+            confidence
+        );
+        return None;
+    }
+    info!(
+        "detected charset {} with confidence {:.2}",
+        encoding.name(),
+        confidence
+    );
+    Some((encoding, confidence))
+}
+
+// Decodes a body to text. The charset comes from the content-type, then a <meta>
+// tag, then the fallback, and defaults to utf-8; a byte order mark overrides all of them.
+pub fn decode_text(
+    bytes: &[u8],
+    content_type: Option<&str>,
+    fallback: &CharsetFallback,
+) -> (String, Charset) {
+    let declared = content_type
+        .and_then(charset_param)
+        .or_else(|| meta_charset(bytes))
+        .or(fallback.assume);
+    let (encoding, confidence) = match declared {
+        Some(encoding) => (encoding, None),
+        None => match detect_charset(bytes, fallback.min_confidence) {
+            Some((encoding, confidence)) => (encoding, Some(confidence)),
+            None => (UTF_8, None),
+        },
+    };
+    let (text, used, _) = encoding.decode(bytes);
+    let charset = Charset {
+        encoding: used,
+        confidence: confidence.filter(|_| used == encoding),
+    };
+    (text.into_owned(), charset)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use encoding_rs::{SHIFT_JIS, UTF_16LE, WINDOWS_1252};
+
+    use super::*;
+
+    const JAPANESE: &str = "日本語のページです。文字化けしないように、シフトJISで書かれた文章を正しく読み込めるかどうかを確かめます。";
+
+    fn no_fallback() -> CharsetFallback {
+        CharsetFallback {
+            assume: None,
+            min_confidence: 0.0,
+        }
+    }
+
+    fn shift_jis(html: &str) -> Vec<u8> {
+        SHIFT_JIS.encode(html).0.into_owned()
+    }
+
+    #[test]
+    fn charset_from_content_type() {
+        let bytes = shift_jis(&format!("<html><body>{}</body></html>", JAPANESE));
+        let (text, charset) =
+            decode_text(&bytes, Some("text/html; charset=Shift_JIS"), &no_fallback());
+        assert!(text.contains(JAPANESE));
+        assert_eq!(charset.encoding, SHIFT_JIS);
+        assert_eq!(charset.confidence, None);
+    }
+
+    #[test]
+    fn content_type_wins_over_meta() {
+        let bytes = shift_jis(&format!(
+            "<html><head><meta charset=\"iso-8859-1\"></head><body>{}</body></html>",
+            JAPANESE
+        ));
+        let (text, charset) = decode_text(&bytes, Some("text/html; charset=sjis"), &no_fallback());
+        assert!(text.contains(JAPANESE));
+        assert_eq!(charset.encoding, SHIFT_JIS);
+    }
+
+    #[test]
+    fn charset_from_meta() {
+        for meta in [
+            "<meta charset=\"shift_jis\">",
+            "<meta http-equiv=\"Content-Type\" content=\"text/html; charset=Shift_JIS\">",
+        ] {
+            let bytes = shift_jis(&format!(
+                "<html><head>{}</head><body>{}</body></html>",
+                meta, JAPANESE
+            ));
+            let (text, charset) = decode_text(&bytes, Some("text/html"), &no_fallback());
+            assert!(text.contains(JAPANESE), "{}", meta);
+            assert_eq!(charset.encoding, SHIFT_JIS, "{}", meta);
+        }
+    }
+
+    #[test]
+    fn assumed_charset_when_undeclared() {
+        let bytes = shift_jis(&format!("<html><body>{}</body></html>", JAPANESE));
+        let fallback = CharsetFallback {
+            assume: Some(SHIFT_JIS),
+            min_confidence: 0.0,
+        };
+        let (text, charset) = decode_text(&bytes, None, &fallback);
+        assert!(text.contains(JAPANESE));
+        assert_eq!(charset.encoding, SHIFT_JIS);
+        assert_eq!(charset.confidence, None);
+    }
+
+    #[test]
+    fn detected_charset_when_undeclared() {
+        let bytes = shift_jis(&format!("<html><body>{}</body></html>", JAPANESE.repeat(5)));
+        let (text, charset) = decode_text(&bytes, None, &no_fallback());
+        assert!(text.contains(JAPANESE));
+        assert_eq!(charset.encoding, SHIFT_JIS);
+        assert!(charset.confidence.is_some());
+    }
+
+    #[test]
+    fn unsure_detection_falls_back_to_utf8() {
+        let bytes = WINDOWS_1252.encode("<p>caf\u{e9}</p>").0.into_owned();
+        let fallback = CharsetFallback {
+            assume: None,
+            // no guess is ever this sure
+            min_confidence: 1.1,
+        };
+        let (text, charset) = decode_text(&bytes, None, &fallback);
+        assert_eq!(charset.encoding, UTF_8);
+        assert_eq!(text, "<p>caf\u{fffd}</p>");
+    }
+
+    #[test]
+    fn latin1_round_trip() {
+        let html = "<html><head><meta charset=\"iso-8859-1\"></head>\
+                    <body>Caf\u{e9} cr\u{e8}me br\u{fb}l\u{e9}e \u{a9} \u{bd}</body></html>";
+        let bytes = WINDOWS_1252.encode(html).0.into_owned();
+        // one byte per character, nothing was escaped
+        assert_eq!(bytes.len(), html.chars().count());
+        for content_type in [
+            Some("text/html; charset=ISO-8859-1"),
+            Some("text/html"),
+            None,
+        ] {
+            let (text, charset) = decode_text(&bytes, content_type, &no_fallback());
+            assert_eq!(text, html, "{:?}", content_type);
+            // encoding_rs treats the iso-8859-1 label as its superset windows-1252
+            assert_eq!(charset.encoding, WINDOWS_1252);
+            assert_eq!(charset.encoding.encode(&text).0, bytes);
+        }
+    }
+
+    #[test]
+    fn byte_order_mark_wins() {
+        let mut bytes = vec![0xff, 0xfe];
+        bytes.extend("<p>bom</p>".encode_utf16().flat_map(u16::to_le_bytes));
+        let (text, charset) = decode_text(&bytes, Some("text/html; charset=utf-8"), &no_fallback());
+        assert_eq!(text, "<p>bom</p>");
+        assert_eq!(charset.encoding, UTF_16LE);
+    }
+
+    const HTML: &[u8] = b"<html><body>compressed caf\xc3\xa9</body></html>";
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn inflate(data: &[u8], content_encoding: &str) -> Vec<u8> {
+        decompress_if_needed(data, content_encoding, None)
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn gzip_round_trip() {
+        let compressed = gzip(HTML);
+        assert_ne!(compressed, HTML);
+        assert_eq!(inflate(&compressed, "gzip"), HTML);
+        assert_eq!(inflate(&compressed, "X-GZIP"), HTML);
+    }
+
+    #[test]
+    fn deflate_round_trip_zlib_or_raw() {
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        zlib.write_all(HTML).unwrap();
+        let mut raw = flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+        raw.write_all(HTML).unwrap();
+        for compressed in [zlib.finish().unwrap(), raw.finish().unwrap()] {
+            assert_eq!(inflate(&compressed, "deflate"), HTML);
+        }
+    }
+
+    #[test]
+    fn brotli_round_trip() {
+        let mut compressed = vec![];
+        brotli::CompressorWriter::new(&mut compressed, 4096, 5, 22)
+            .write_all(HTML)
+            .unwrap();
+        assert_ne!(compressed, HTML);
+        assert_eq!(inflate(&compressed, "br"), HTML);
+        // layers are undone from the last one applied
+        let stacked = gzip(&compressed);
+        assert_eq!(inflate(&stacked, "br, gzip"), HTML);
+    }
+
+    #[test]
+    fn identity_and_unknown_encodings_keep_the_body() {
+        assert_eq!(inflate(HTML, ""), HTML);
+        assert_eq!(inflate(HTML, "identity"), HTML);
+        let compressed = gzip(HTML);
+        assert_eq!(inflate(&compressed, "gzip, zstd"), compressed);
+    }
+
+    #[test]
+    fn inflating_stops_at_the_limit() {
+        // 16MB of zeros is about 16KB of gzip
+        let bomb = gzip(&vec![0; 16 << 20]);
+        assert!(bomb.len() < 32 << 10);
+        assert_eq!(
+            decompress_if_needed(&bomb, "gzip", Some(1 << 20)).unwrap(),
+            None
+        );
+        let stacked = gzip(&bomb);
+        assert_eq!(
+            decompress_if_needed(&stacked, "gzip, gzip", Some(1 << 20)).unwrap(),
+            None
+        );
+        let exact = gzip(HTML);
+        assert_eq!(
+            decompress_if_needed(&exact, "gzip", Some(HTML.len())).unwrap(),
+            Some(HTML.to_vec())
+        );
+        assert_eq!(
+            decompress_if_needed(&exact, "gzip", Some(HTML.len() - 1)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn html_content_types() {
+        assert!(is_html("text/html"));
+        assert!(is_html("TEXT/HTML"));
+        assert!(is_html("Text/Html"));
+        assert!(is_html("text/html; charset=utf-8"));
+        assert!(is_html("Text/HTML; Charset=UTF-8"));
+        assert!(is_html(" text/html ;charset=\"utf-8\""));
+        assert!(!is_html("text/plain"));
+        assert!(!is_html("application/xhtml+xml"));
+        assert!(!is_html("text/html5"));
+        assert!(!is_html(""));
+    }
+
+    // A file of the test's own holding `content`
+    fn temp_file(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rget-{}-{}", name, std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn header_file_skips_comments_and_blank_lines() {
+        let path = temp_file(
+            "headers",
+            "# sent with every request\r\n\
+             X-Api-Key: abc\r\n\
+             \r\n\
+             \x20 # indented comment\n\
+             \x20\x20\n\
+             Accept-Language:  en-GB  \n",
+        );
+        let headers = read_header_file(&path).unwrap();
+        let headers: Vec<_> = headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            headers,
+            [("x-api-key", "abc"), ("accept-language", "en-GB")]
+        );
+    }
+
+    #[test]
+    fn header_file_rejects_malformed_lines() {
+        let path = temp_file("bad-headers", "X-Good: yes\nno colon here\n");
+        let error = read_header_file(&path).unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("\"no colon here\""),
+            "{:#}",
+            error
+        );
+        let path = temp_file("bad-header-name", "Bad Name: yes\n");
+        assert!(read_header_file(&path).is_err());
+        assert!(read_header_file("/nonexistent/rget-headers").is_err());
+    }
+
+    #[test]
+    fn user_agents_take_turns() {
+        let path = temp_file(
+            "user-agents",
+            "# rotated\nagent-a\n\n  agent-b  \nagent-c\n",
+        );
+        let agents = UserAgents::from_file(&path).unwrap();
+        let sent: Vec<_> = (0..7).map(|_| agents.next().unwrap()).collect();
+        assert_eq!(
+            sent,
+            ["agent-a", "agent-b", "agent-c", "agent-a", "agent-b", "agent-c", "agent-a"]
+        );
+        assert_eq!(UserAgents::default().next(), None);
+        let path = temp_file("no-user-agents", "# nothing\n\n");
+        assert!(UserAgents::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn listed_hosts_are_upgraded_to_https() {
+        let hosts = ["example.com".to_owned()];
+        for (url, expected) in [
+            ("http://example.com/a?b#c", "https://example.com/a?b#c"),
+            // the default port of http becomes the default port of https
+            ("http://example.com:80/", "https://example.com/"),
+            ("http://example.com:443/", "https://example.com/"),
+            ("http://example.com:8080/", "https://example.com:8080/"),
+            ("http://www.Example.COM/", "https://www.example.com/"),
+            ("https://example.com/", "https://example.com/"),
+            ("http://notexample.com/", "http://notexample.com/"),
+            ("http://example.com.evil/", "http://example.com.evil/"),
+            ("ftp://example.com/", "ftp://example.com/"),
+        ] {
+            let mut upgraded = Url::parse(url).unwrap();
+            upgrade_to_https(&mut upgraded, &hosts);
+            assert_eq!(upgraded.as_str(), expected, "{}", url);
+        }
+    }
+}
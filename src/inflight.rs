@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    pin::pin,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use reqwest::Url;
+use tokio::sync::Notify;
+
+use crate::error::FetchError;
+
+// How the leader's fetch ended, shared with the tasks that waited on it
+pub type Outcome = Result<(), FetchError>;
+
+#[derive(Default)]
+struct Flight {
+    done: Notify,
+    outcome: OnceLock<Outcome>,
+}
+
+// Coalesces concurrent fetches of the same url: the first caller does the
+// work, later callers wait for it to finish and reuse its output.
+#[derive(Default)]
+pub struct InFlight {
+    requests: Mutex<HashMap<Url, Arc<Flight>>>,
+}
+
+pub struct Leader<'a> {
+    inflight: &'a InFlight,
+    url: Url,
+    flight: Arc<Flight>,
+}
+
+pub enum Joined<'a> {
+    // the caller fetches the url itself, and reports how it went to finish()
+    Leader(Leader<'a>),
+    // the outcome of the concurrent fetch, None if it was abandoned
+    Waited(Option<Outcome>),
+}
+
+impl InFlight {
+    // Returns a guard if the caller should fetch `url` itself, or waits for
+    // the fetch already in flight and returns its outcome.
+    pub async fn join(&self, url: &Url) -> Joined<'_> {
+        let flight = {
+            let mut requests = self.requests.lock().unwrap();
+            match requests.get(url) {
+                Some(flight) => flight.clone(),
+                None => {
+                    let flight = Arc::new(Flight::default());
+                    requests.insert(url.clone(), flight.clone());
+                    return Joined::Leader(Leader {
+                        inflight: self,
+                        url: url.clone(),
+                        flight,
+                    });
+                }
+            }
+        };
+        let mut notified = pin!(flight.done.notified());
+        // a Notified only hears notify_waiters() once it's registered, so
+        // register before looking again. The leader removes its entry before
+        // notifying: if that same entry is still here, the wakeup is still to
+        // come. A different entry belongs to a later leader that won't wake us.
+        notified.as_mut().enable();
+        let running = self
+            .requests
+            .lock()
+            .unwrap()
+            .get(url)
+            .is_some_and(|current| Arc::ptr_eq(current, &flight));
+        if running {
+            notified.await;
+        }
+        let outcome = flight.outcome.get().map(|outcome| match outcome {
+            Ok(()) => Ok(()),
+            Err(e) => Err(e.duplicate()),
+        });
+        Joined::Waited(outcome)
+    }
+}
+
+impl Leader<'_> {
+    // Publishes the outcome to the waiting tasks, then wakes them
+    pub fn finish(self, outcome: Outcome) {
+        self.flight.outcome.set(outcome).ok();
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        let flight = self.inflight.requests.lock().unwrap().remove(&self.url);
+        if let Some(flight) = flight {
+            flight.done.notify_waiters();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use tokio::sync::Barrier;
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_joins_fetch_once() {
+        let url = Url::parse("https://example.com/").unwrap();
+        for _ in 0..50 {
+            let inflight = Arc::new(InFlight::default());
+            let fetches = Arc::new(AtomicUsize::new(0));
+            let barrier = Arc::new(Barrier::new(10));
+            let tasks: Vec<_> = (0..10)
+                .map(|_| {
+                    let (inflight, fetches, barrier, url) = (
+                        inflight.clone(),
+                        fetches.clone(),
+                        barrier.clone(),
+                        url.clone(),
+                    );
+                    tokio::spawn(async move {
+                        barrier.wait().await;
+                        if let Joined::Leader(leader) = inflight.join(&url).await {
+                            fetches.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(20)).await;
+                            leader.finish(Ok(()));
+                        }
+                    })
+                })
+                .collect();
+            let all = futures::future::join_all(tasks);
+            tokio::time::timeout(Duration::from_secs(5), all)
+                .await
+                .expect("a waiter was never woken");
+            assert_eq!(fetches.load(Ordering::SeqCst), 1);
+            assert!(inflight.requests.lock().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_new_leader_after_the_first_finished() {
+        let inflight = InFlight::default();
+        let url = Url::parse("https://example.com/").unwrap();
+        let Joined::Leader(leader) = inflight.join(&url).await else {
+            panic!("nothing was in flight");
+        };
+        leader.finish(Ok(()));
+        assert!(matches!(inflight.join(&url).await, Joined::Leader(_)));
+    }
+
+    #[tokio::test]
+    async fn waiters_get_the_leaders_outcome() {
+        let inflight = InFlight::default();
+        let url = Url::parse("https://example.com/").unwrap();
+        let Joined::Leader(leader) = inflight.join(&url).await else {
+            panic!("nothing was in flight");
+        };
+        let waiter = inflight.join(&url);
+        let finish = async {
+            tokio::task::yield_now().await;
+            leader.finish(Err(FetchError::Http {
+                url: url.clone(),
+                status: reqwest::StatusCode::NOT_FOUND,
+            }));
+        };
+        let (joined, ()) = tokio::join!(waiter, finish);
+        match joined {
+            Joined::Waited(Some(Err(FetchError::Http { status, .. }))) => {
+                assert_eq!(status, 404)
+            }
+            _ => panic!("the failure wasn't passed on"),
+        }
+    }
+
+    #[tokio::test]
+    async fn an_abandoned_fetch_has_no_outcome() {
+        let inflight = InFlight::default();
+        let url = Url::parse("https://example.com/").unwrap();
+        let leader = inflight.join(&url).await;
+        let waiter = inflight.join(&url);
+        let abandon = async {
+            tokio::task::yield_now().await;
+            drop(leader);
+        };
+        let (joined, ()) = tokio::join!(waiter, abandon);
+        assert!(matches!(joined, Joined::Waited(None)));
+    }
+}
@@ -0,0 +1,3389 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use clap::{Arg, ArgMatches, Command};
+use encoding_rs::{Encoding, ISO_2022_JP, UTF_8};
+use futures::{stream::FuturesUnordered, StreamExt};
+use rand::seq::SliceRandom;
+use regex::Regex;
+use reqwest::{
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCEPT_LANGUAGE,
+        AUTHORIZATION, CONTENT_ENCODING, CONTENT_LANGUAGE, CONTENT_TYPE, USER_AGENT,
+    },
+    Client, Response, StatusCode, Url,
+};
+use sha2::{Digest, Sha256};
+use tl::{parse, ParserOptions};
+use tokio::{fs::File, io::AsyncWriteExt, sync::Semaphore};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+mod browser;
+mod checksums;
+mod dedup;
+#[cfg(feature = "doh")]
+mod doh;
+mod error;
+mod format;
+mod frontier;
+mod graph;
+mod har;
+mod headless;
+mod hosts;
+mod http;
+mod inflight;
+mod links;
+mod manifest;
+mod metadata;
+mod metrics;
+mod netrc;
+mod output;
+mod probe;
+mod replay;
+mod robots;
+mod stats;
+mod summary;
+mod template;
+mod text;
+mod tls;
+
+use browser::Browser;
+use checksums::Checksums;
+use dedup::{Dedup, LinkMode};
+pub use error::FetchError;
+use format::Layout;
+use frontier::{Frontier, PendingTask};
+use graph::LinkGraph;
+use headless::Headless;
+use hosts::{BreakerConfig, Hosts};
+use http::{Charset, CharsetFallback, HeaderLimits, UserAgents};
+use inflight::{InFlight, Joined};
+use manifest::Change;
+use metadata::Metadata;
+use metrics::{Metrics, MetricsServer};
+use netrc::Netrc;
+use output::{relative_path, resolve_conflict, NameScheme, PathConflict};
+use probe::Probe;
+use robots::Robots;
+use stats::Stats;
+pub use summary::{FetchSummary, FetchedItem};
+use template::OutputTemplate;
+use text::Readability;
+
+// What a run does, built from a command line with Config::from_args
+pub struct Config {
+    show_metadata: bool,
+    rewrite_assets: bool,
+    crawl: bool,
+    // point links between crawled pages to their local copies
+    convert_links: bool,
+    export_urls: Option<PathBuf>,
+    // print discovered urls instead of downloading them
+    extract_links_only: bool,
+    print_tree: bool,
+    graph_dot: Option<PathBuf>,
+    breaker: Option<BreakerConfig>,
+    on_path_conflict: PathConflict,
+    output_template: Option<OutputTemplate>,
+    output_encoding: Option<&'static Encoding>,
+    // make saved pages declare the charset they are saved in
+    declare_encoding: bool,
+    body_only: bool,
+    strip_comments: bool,
+    strip_cdata: bool,
+    strip_scripts: bool,
+    extract_text: bool,
+    // with --extract-text=DIR the text goes there and the page is saved as usual
+    text_dir: Option<PathBuf>,
+    count_words: bool,
+    extract_comments: bool,
+    max_response_time: Option<Duration>,
+    no_parent: bool,
+    // crawl every subdomain of the seeds' domains, not only their hosts
+    include_subdomains: bool,
+    max_concurrent_hosts: Option<usize>,
+    max_time_per_host: Option<Duration>,
+    dedup_link: Option<LinkMode>,
+    ignore_mime: bool,
+    probe_only: bool,
+    json: bool,
+    // print a line of json as each task ends
+    jsonl: bool,
+    keep_integrity: bool,
+    replay_from: Option<PathBuf>,
+    respect_robots: bool,
+    fail_on_mixed_content: bool,
+    http_trace: bool,
+    layout: Option<Layout>,
+    decode_entities: bool,
+    save_original: bool,
+    header_limits: HeaderLimits,
+    prefer_canonical_amp: bool,
+    links_csv: Option<PathBuf>,
+    stall_timeout: Option<Duration>,
+    retry_on_empty: bool,
+    har_output: Option<PathBuf>,
+    // separate records of machine-readable output with NUL instead of newlines
+    null_separated: bool,
+    headless: bool,
+    wait_for_selector: Option<String>,
+    charset_fallback: CharsetFallback,
+    write_index: bool,
+    done_file: Option<PathBuf>,
+    // add a hash of the query string to file names
+    rewrite_query: bool,
+    check_links: bool,
+    // extension => content-type to use instead of the server's
+    type_overrides: HashMap<String, String>,
+    await_assets: bool,
+    // expected digests of downloads, and whether unlisted urls are skipped
+    checksums: Option<Checksums>,
+    // per-host credentials for basic auth
+    netrc: Option<Netrc>,
+    verify_strict: bool,
+    metrics_port: Option<u16>,
+    metrics_addr: IpAddr,
+    // queued and running tasks past which discovered urls are dropped
+    max_queue_depth: Option<usize>,
+    output_manifest: Option<PathBuf>,
+    // manifest of an earlier run, files whose content didn't change are kept
+    since: Option<manifest::Previous>,
+    // lazy-loading attributes rewritten like src, with --include-lazy-attrs
+    lazy_attrs: Vec<String>,
+    // also rewrite the documents inlined in <iframe srcdoc>
+    rewrite_srcdoc: bool,
+    // reject urls browsers would have to fix up
+    strict_urls: bool,
+    // resolve relative links of redirected pages against where they ended up
+    base_on_final: bool,
+    // where the saved copy will be hosted, for absolute urls to the site
+    rewrite_base: Option<Url>,
+    // pages are only saved when their <title> matches title_filter and
+    // doesn't match title_exclude
+    title_filter: Option<Regex>,
+    title_exclude: Option<Regex>,
+    // hosts only ever fetched over https, subdomains included
+    https_only_hosts: Vec<String>,
+    // header carrying a fresh uuid with every request
+    request_id_header: Option<HeaderName>,
+    // don't save pages that turn out not to be html
+    html_only: bool,
+    // report pages without a viewport sized to the device
+    mobile_check: bool,
+    // pages larger than this are saved as is instead of parsed
+    max_parse_size: Option<usize>,
+    // bytes of each parsed page that are saved, the <head> is often enough
+    truncate_html: Option<usize>,
+    name_scheme: NameScheme,
+    // sent with every request: a simulated browser's, then the negotiation
+    // headers, then --header values
+    headers: HeaderMap,
+    // ask for compressed bodies, and have the client undo the compression
+    brotli: bool,
+    redirect_same_host_only: bool,
+    proxy: Option<reqwest::Proxy>,
+    no_verify_ssl_hostname: bool,
+    #[cfg_attr(not(feature = "http3"), allow(dead_code))]
+    http3: bool,
+    #[cfg_attr(not(feature = "doh"), allow(dead_code))]
+    doh: Option<String>,
+    user_agent_file: Option<String>,
+    parallel_writes: Option<usize>,
+    randomise_order: bool,
+    // a first Ctrl-C lets the tasks in progress finish
+    graceful_shutdown: bool,
+    // where the queue is checkpointed, and resumed from
+    frontier_file: Option<PathBuf>,
+    // urls to post url-encoded form data to, with the data
+    posts: Vec<(Url, String)>,
+}
+
+impl Config {
+    // whether pages have to go through filter_html
+    fn parses_pages(&self) -> bool {
+        self.show_metadata
+            || self.rewrite_assets
+            || self.crawl
+            || self.output_encoding.is_some()
+            || self.declare_encoding
+            || self.rewrite_base.is_some()
+            || self.title_filter.is_some()
+            || self.title_exclude.is_some()
+            || self.mobile_check
+            || self.body_only
+            || self.strip_comments
+            || self.strip_cdata
+            || self.strip_scripts
+            || self.extract_text
+            || self.count_words
+            || self.extract_comments
+            || self.fail_on_mixed_content
+            || self.layout.is_some()
+            || self.prefer_canonical_amp
+            || self.links_csv.is_some()
+            || self.check_links
+            || self.truncate_html.is_some()
+    }
+
+    // A url given on the command line, parsed as --strict-urls asks
+    pub fn parse_seed(&self, url: &str) -> Result<Url> {
+        parse_url(None, url, self.strict_urls)
+    }
+
+    // only discovering urls, nothing gets saved
+    fn exporting(&self) -> bool {
+        self.export_urls.is_some() || self.extract_links_only
+    }
+
+    // the link graph is only kept when something is going to show it
+    fn graphing(&self) -> bool {
+        self.print_tree || self.graph_dot.is_some()
+    }
+
+    // a url as it will be requested
+    fn normalize(&self, mut url: Url) -> Url {
+        http::upgrade_to_https(&mut url, &self.https_only_hosts);
+        url
+    }
+
+    fn out_name(&self, url: &Url) -> PathBuf {
+        let name = match self.name_scheme {
+            NameScheme::Flat => filename_for_url(url, self.rewrite_query),
+            NameScheme::Hash => hashed_filename(url),
+        };
+        match &self.output_template {
+            Some(template) => template.render(url, &name).into(),
+            None => name.into(),
+        }
+    }
+}
+
+// state shared by all tasks of a run
+#[derive(Default)]
+struct State {
+    client: Client,
+    // what the client sends with every request, for --http-trace
+    default_headers: HeaderMap,
+    hosts: Hosts,
+    inflight: InFlight,
+    stats: Stats,
+    dedup: Dedup,
+    probes: Mutex<Vec<Probe>>,
+    robots: Robots,
+    user_agents: UserAgents,
+    links: Mutex<Vec<links::Link>>,
+    // response status of every url fetched or link checked, for --links-csv
+    statuses: Mutex<HashMap<Url, u16>>,
+    har: Mutex<Vec<har::Entry>>,
+    headless: Headless,
+    link_checker: links::Checker,
+    // shared with the --metrics-port server
+    metrics: Arc<Metrics>,
+    // bounds how many files are written at once, with --parallel-writes
+    write_slots: Option<Semaphore>,
+    // assets fetched by their pages with --await-assets
+    awaited: Mutex<HashSet<Url>>,
+    // files saved so far, for --write-index and --output-manifest
+    saved: Mutex<Vec<manifest::Entry>>,
+    // file and size of tasks that saved one, until --jsonl reports them
+    written: Mutex<HashMap<Url, (PathBuf, usize)>>,
+}
+
+// what filter_html made of a response
+struct Filtered {
+    body: Vec<u8>,
+    tasks: Vec<Task>,
+    // body is the extracted text of the page rather than the page itself
+    text: bool,
+    // the page as fetched, kept for --save-original and --verify-against
+    // when body differs from it
+    fetched: Option<Vec<u8>>,
+    // nothing to save: an amp page whose canonical version is fetched
+    // instead, or a page left out by its title
+    replaced: bool,
+}
+
+impl Filtered {
+    fn raw(body: Vec<u8>) -> Self {
+        Self {
+            body,
+            tasks: vec![],
+            text: false,
+            fetched: None,
+            replaced: false,
+        }
+    }
+}
+
+struct Task {
+    url: Url,
+    // pages get parsed as html, everything else is saved as is
+    page: bool,
+    parent: Option<Url>,
+    // url-encoded form data to post instead of a plain get
+    form: Option<String>,
+    // links away from the seed this task was discovered through
+    depth: usize,
+    // the amp page this canonical page stands in for
+    amp: Option<Url>,
+    // already fetched by the page that found it, with --await-assets
+    done: bool,
+    // where the request was redirected to, relative links are resolved
+    // against it with --base-on-final
+    base: Option<Url>,
+}
+
+// filesystems cap names at 255 bytes, leave room for suffixes like `.tmp`
+const MAX_FILENAME_LEN: usize = 200;
+// how often --retry-on-empty asks again, and how long it first waits
+const EMPTY_RETRIES: u32 = 3;
+const EMPTY_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+// how often a crawl with --frontier-file checkpoints its queue
+const FRONTIER_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+// comments kept per page by --extract-comments
+const MAX_COMMENTS: usize = 1000;
+// how deep --rewrite-srcdoc follows srcdoc documents inside srcdoc documents
+const MAX_SRCDOC_DEPTH: usize = 8;
+
+fn short_hash(data: &str) -> String {
+    Sha256::digest(data.as_bytes())[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Cuts the middle out of an overlong name, keeping its extension and adding a
+// hash of the url so truncated names stay unique
+fn truncate_filename(name: String, url: &Url) -> String {
+    if name.len() <= MAX_FILENAME_LEN {
+        return name;
+    }
+    let ext = match name.rfind('.') {
+        Some(i) if name.len() - i <= 10 && !name[i..].contains('_') => &name[i..],
+        _ => "",
+    };
+    let hash = short_hash(url.as_str());
+    let mut keep = MAX_FILENAME_LEN - ext.len() - hash.len() - 3;
+    while !name.is_char_boundary(keep) {
+        keep -= 1;
+    }
+    let short = format!("{}...{}{}", &name[..keep], hash, ext);
+    warn!("filename for {} is too long, using {}", url, short);
+    short
+}
+
+// `<sha256 of the url>.<ext>`, the extension is the one of the url's path if
+// it looks like one
+fn hashed_filename(url: &Url) -> String {
+    let hash: String = Sha256::digest(url.as_str().as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let last = url.path_segments().and_then(|mut s| s.next_back());
+    match last.and_then(|name| name.rsplit_once('.')) {
+        Some((_, ext))
+            if (1..=10).contains(&ext.len()) && ext.bytes().all(|b| b.is_ascii_alphanumeric()) =>
+        {
+            format!("{}.{}", hash, ext.to_ascii_lowercase())
+        }
+        _ => hash,
+    }
+}
+
+// Parses `href` the way browsers do, which escapes spaces, quotes, brackets
+// and the like instead of failing. With `strict`, urls that needed any such
+// fixing up are rejected.
+fn parse_url(base: Option<&Url>, href: &str, strict: bool) -> Result<Url> {
+    let violation = Cell::new(None);
+    let url = Url::options()
+        .base_url(base)
+        .syntax_violation_callback(Some(&|v| violation.set(Some(v.description()))))
+        .parse(href)?;
+    match violation.get() {
+        Some(violation) if strict => Err(anyhow!(violation)),
+        _ => Ok(url),
+    }
+}
+
+fn filename_for_url(url: &Url, with_query: bool) -> String {
+    let name = flat_filename(url);
+    let name = match url.query().filter(|q| with_query && !q.is_empty()) {
+        Some(query) => query_suffixed(name, query),
+        None => name,
+    };
+    truncate_filename(name, url)
+}
+
+// `example.com_image.jpg` => `example.com_image_a1b2c3d4.jpg`, so versions of an
+// asset that only differ by query string get names of their own
+fn query_suffixed(name: String, query: &str) -> String {
+    let hash = short_hash(query);
+    match name.rfind('.') {
+        Some(i) if !name[i..].contains('_') => format!("{}_{}{}", &name[..i], hash, &name[i..]),
+        _ => format!("{}_{}", name, hash),
+    }
+}
+
+fn flat_filename(url: &Url) -> String {
+    let path = PathBuf::from(url.path());
+    if path.file_name().is_none() {
+        format!("{}.html", url.host_str().unwrap())
+    } else {
+        format!(
+            "{}{}",
+            url.host_str().unwrap(),
+            url.path().replace("/", "_")
+        )
+    }
+}
+
+// Whether a failed request counts against its host's circuit: a 404 is a
+// broken link, connection errors, timeouts, 5xx and 429 are the host failing
+fn is_host_failure(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<FetchError>() {
+        Some(FetchError::Http { status, .. }) => {
+            status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+        }
+        _ => true,
+    }
+}
+
+fn header_str(resp: &Response, name: HeaderName) -> Option<String> {
+    resp.headers().get(name)?.to_str().ok().map(str::to_owned)
+}
+
+// `page.html` => `page.txt`, anything else gets .txt appended
+fn text_name(path: &Path) -> PathBuf {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("html" | "htm") => path.with_extension("txt"),
+        _ => {
+            let mut name = path.as_os_str().to_owned();
+            name.push(".txt");
+            name.into()
+        }
+    }
+}
+
+fn strip_cdata(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("<![CDATA[") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start..].find("]]>") {
+            Some(end) => &rest[start + end + 3..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+fn strip_scripts(html: &str) -> String {
+    // ascii lowercasing keeps byte offsets intact
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find("<script").map(|i| pos + i) {
+        let after = lower[start + 7..].chars().next();
+        if !matches!(after, Some(c) if c == '>' || c == '/' || c.is_ascii_whitespace()) {
+            out.push_str(&html[pos..start + 7]);
+            pos = start + 7;
+            continue;
+        }
+        out.push_str(&html[pos..start]);
+        pos = lower[start..]
+            .find("</script")
+            .and_then(|end| {
+                lower[start + end..]
+                    .find('>')
+                    .map(|gt| start + end + gt + 1)
+            })
+            .unwrap_or(html.len());
+    }
+    out.push_str(&html[pos..]);
+    out
+}
+
+fn strip_event_handlers(t: &mut tl::HTMLTag) -> Result<()> {
+    let attrs = t.attributes_mut();
+    let handlers: Vec<String> = attrs
+        .iter()
+        .map(|(k, _)| k.into_owned())
+        .filter(|k| k.len() > 2 && k[..2].eq_ignore_ascii_case("on"))
+        .collect();
+    for handler in handlers {
+        attrs.remove(tl::Bytes::try_from(handler)?);
+    }
+    Ok(())
+}
+
+fn body_inner_html(dom: &tl::VDom) -> Option<String> {
+    let parser = dom.parser();
+    let body = dom.query_selector("body")?.next()?.get(parser)?;
+    let html = body
+        .children()?
+        .top()
+        .iter()
+        .filter_map(|child| child.get(parser))
+        .map(|child| child.inner_html(parser))
+        .collect();
+    Some(html)
+}
+
+// Attribute holding the url of an asset we download and rewrite for `t`
+fn asset_attr(t: &tl::HTMLTag) -> Option<&'static str> {
+    match t.name().as_utf8_str().as_ref() {
+        "img" | "script" | "audio" => Some("src"),
+        "video" => Some("poster"),
+        "link" => {
+            let rel = t
+                .attributes()
+                .get("rel")
+                .flatten()?
+                .as_utf8_str()
+                .to_ascii_lowercase();
+            rel.split_whitespace()
+                .any(|r| r == "stylesheet" || r == "icon")
+                .then_some("href")
+        }
+        // the legacy background image of <body>, <table> and its cells
+        _ if t.attributes().contains("background") => Some("background"),
+        _ => None,
+    }
+}
+
+// All attributes of `t` holding asset urls: the usual one, then any of the
+// lazy-loading attributes it has
+fn asset_attrs<'a>(t: &tl::HTMLTag, lazy_attrs: &'a [String]) -> Vec<&'a str> {
+    let mut attrs: Vec<&str> = asset_attr(t).into_iter().collect();
+    // <video src> is a media file like <audio src>, poster is only its preview
+    if t.name().as_utf8_str() == "video" && t.attributes().contains("src") {
+        attrs.push("src");
+    }
+    let present = lazy_attrs
+        .iter()
+        .filter(|a| t.attributes().contains(a.as_str()));
+    attrs.extend(present.map(String::as_str));
+    attrs
+}
+
+// `a.jpg 1x, b.jpg 2x` => [("a.jpg", "1x"), ("b.jpg", "2x")]
+fn srcset_candidates(srcset: &str) -> Vec<(&str, &str)> {
+    srcset
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| c.split_once(char::is_whitespace).unwrap_or((c, "")))
+        .collect()
+}
+
+// Returns whether `t` was a charset declaration
+fn set_meta_charset(t: &mut tl::HTMLTag, encoding: &'static Encoding) -> Result<bool> {
+    let attrs = t.attributes_mut();
+    if let Some(charset) = attrs.get_mut("charset").flatten() {
+        charset.set(encoding.name())?;
+    } else if attrs
+        .get("http-equiv")
+        .flatten()
+        .is_some_and(|v| v.as_utf8_str().eq_ignore_ascii_case("content-type"))
+    {
+        if let Some(content) = attrs.get_mut("content").flatten() {
+            content.set(format!("text/html; charset={}", encoding.name()))?;
+        }
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+// Offset right after the first `<name ...>` tag of lowercased html
+fn after_open_tag(lower: &str, name: &str) -> Option<usize> {
+    let open = format!("<{}", name);
+    let (start, _) = lower.match_indices(&open).find(|(i, _)| {
+        // `<head>` but not `<header>`
+        matches!(
+            lower.as_bytes().get(i + open.len()),
+            Some(b'>' | b'/' | b' ' | b'\t' | b'\n' | b'\r')
+        )
+    })?;
+    lower[start..].find('>').map(|end| start + end + 1)
+}
+
+// Adds `<meta charset>` at the start of the <head>, or as early as possible
+// without putting it before the doctype if there is none
+fn insert_meta_charset(html: &str, encoding: &'static Encoding) -> String {
+    let meta = format!("<meta charset=\"{}\">", encoding.name());
+    let lower = html.to_ascii_lowercase();
+    let at = after_open_tag(&lower, "head")
+        .or_else(|| after_open_tag(&lower, "html"))
+        .or_else(|| after_open_tag(&lower, "!doctype"))
+        .unwrap_or_default();
+    format!("{}{}{}", &html[..at], meta, &html[at..])
+}
+
+// The longest start of `text` that takes at most `max` bytes in `encoding`,
+// without cutting a character or a character reference in half, and whether
+// that is all of it
+fn encode_truncated(text: &str, encoding: &'static Encoding, max: usize) -> (Vec<u8>, bool) {
+    // room to switch a stateful encoding back to ascii at the end
+    let max = if encoding == ISO_2022_JP {
+        max.saturating_sub(3)
+    } else {
+        max
+    };
+    let mut encoder = encoding.new_encoder();
+    let mut out = Vec::with_capacity(max);
+    // fits any character, as a reference or after an escape sequence
+    let mut encoded = [0; 32];
+    let mut utf8 = [0; 4];
+    let mut complete = true;
+    for c in text.chars() {
+        let (_, _, written, _) =
+            encoder.encode_from_utf8(c.encode_utf8(&mut utf8), &mut encoded, false);
+        if out.len() + written > max {
+            complete = false;
+            break;
+        }
+        out.extend_from_slice(&encoded[..written]);
+    }
+    let (_, _, written, _) = encoder.encode_from_utf8("", &mut encoded, true);
+    out.extend_from_slice(&encoded[..written]);
+    (out, complete)
+}
+
+impl Task {
+    fn new(url: Url) -> Self {
+        Self {
+            url,
+            page: false,
+            parent: None,
+            form: None,
+            depth: 0,
+            amp: None,
+            done: false,
+            base: None,
+        }
+    }
+
+    fn page(url: Url) -> Self {
+        Self {
+            page: true,
+            ..Self::new(url)
+        }
+    }
+
+    fn pending(&self) -> PendingTask {
+        PendingTask {
+            url: self.url.to_string(),
+            page: self.page,
+            form: self.form.clone(),
+            depth: self.depth,
+        }
+    }
+
+    fn resume(pending: PendingTask) -> Result<Self> {
+        Ok(Self {
+            page: pending.page,
+            form: pending.form,
+            depth: pending.depth,
+            ..Self::new(Url::parse(&pending.url)?)
+        })
+    }
+
+    async fn filter_noop(&self, resp: Response, config: &Config) -> Result<Vec<u8>> {
+        http::read_body(resp, config.stall_timeout).await
+    }
+
+    async fn filter_html(
+        &self,
+        resp: Response,
+        config: &Config,
+        state: &State,
+    ) -> Result<Filtered> {
+        // Ensure we are getting an html document
+        let version = resp.version();
+        let content_language = header_str(&resp, CONTENT_LANGUAGE);
+        // an override for the extension wins over what the server says
+        let content_type = match http::type_override(&self.url, &config.type_overrides) {
+            Some(mime) => {
+                debug!("treating {} as {}", self.url, mime);
+                Some(mime.to_owned())
+            }
+            None => header_str(&resp, CONTENT_TYPE),
+        };
+        let is_html = content_type.as_ref().is_some_and(|ct| http::is_html(ct));
+        if !is_html && !config.ignore_mime {
+            warn!("skipping non-html document");
+            Ok(Filtered::raw(
+                http::read_body(resp, config.stall_timeout).await?,
+            ))
+        } else if config
+            .max_parse_size
+            .is_some_and(|max| resp.content_length().is_some_and(|len| len > max as u64))
+        {
+            warn!("{} is too large to parse, saving it as is", self.url);
+            Ok(Filtered::raw(
+                http::read_body(resp, config.stall_timeout).await?,
+            ))
+        } else {
+            let content_encoding = header_str(&resp, CONTENT_ENCODING).unwrap_or_default();
+            let bytes = http::read_body(resp, config.stall_timeout).await?;
+            // without a Content-Length it's only known once read
+            if config.max_parse_size.is_some_and(|max| bytes.len() > max) {
+                warn!("{} is too large to parse, saving it as is", self.url);
+                return Ok(Filtered::raw(bytes));
+            }
+            let bytes =
+                match http::decompress_if_needed(&bytes, &content_encoding, config.max_parse_size)?
+                {
+                    Some(bytes) => bytes,
+                    None => {
+                        warn!(
+                            "{} inflates past the parse limit, saving it as is",
+                            self.url
+                        );
+                        return Ok(Filtered::raw(bytes));
+                    }
+                };
+            let fetched =
+                (config.save_original || config.checksums.is_some()).then(|| bytes.clone());
+            let (mut body, charset) = if is_html {
+                http::decode_text(&bytes, content_type.as_deref(), &config.charset_fallback)
+            } else {
+                debug!("parsing non-html document {} anyway", self.url);
+                match String::from_utf8(bytes) {
+                    Ok(body) => (
+                        body,
+                        Charset {
+                            encoding: UTF_8,
+                            confidence: None,
+                        },
+                    ),
+                    Err(e) => return Ok(Filtered::raw(e.into_bytes())),
+                }
+            };
+            if config.strip_cdata {
+                // tl doesn't understand CDATA sections, so drop them before parsing
+                body = strip_cdata(&body);
+            }
+            if config.strip_scripts {
+                // tl can't parse script contents reliably, so remove them as text
+                body = strip_scripts(&body);
+            }
+            let mut dom = match parse(body.as_str(), ParserOptions::default()) {
+                Ok(dom) => dom,
+                Err(e) if !is_html => {
+                    debug!("{} doesn't parse as html ({}), saving as is", self.url, e);
+                    return Ok(Filtered::raw(body.as_bytes().to_vec()));
+                }
+                Err(source) => {
+                    return Err(FetchError::Parse {
+                        url: self.url.clone(),
+                        source,
+                    }
+                    .into())
+                }
+            };
+            if config.title_filter.is_some() || config.title_exclude.is_some() {
+                let title = metadata::title(&dom)
+                    .map(|title| html_escape::decode_html_entities(&title).into_owned())
+                    .unwrap_or_default();
+                let wanted = config
+                    .title_filter
+                    .as_ref()
+                    .is_none_or(|re| re.is_match(&title))
+                    && !config
+                        .title_exclude
+                        .as_ref()
+                        .is_some_and(|re| re.is_match(&title));
+                if !wanted {
+                    info!("skipping {}, its title is {:?}", self.url, title);
+                    return Ok(Filtered {
+                        body: vec![],
+                        tasks: vec![],
+                        text: false,
+                        fetched: None,
+                        replaced: true,
+                    });
+                }
+            }
+            let mut counts = HashMap::new();
+            let mut tasks = vec![];
+            let mut mixed = 0;
+            let mut comments = vec![];
+            let mut comments_capped = false;
+            // whether the page has a <meta> telling its charset
+            let mut declared = false;
+            let exporting = config.exporting();
+
+            // Just loop on every nodes, we don't care about the hierarchy.
+            // tl parses the content of <noscript> as regular tags, so lazy-load
+            // fallback images in there are picked up by this loop as well.
+            for n in dom.nodes_mut() {
+                if let Some(c) = n.as_comment_mut() {
+                    if config.extract_comments {
+                        if comments.len() < MAX_COMMENTS {
+                            comments.push(metadata::comment_text(&c.as_utf8_str()));
+                        } else {
+                            comments_capped = true;
+                        }
+                    }
+                    if config.strip_comments {
+                        c.set("")?;
+                    }
+                }
+                if let Some(t) = n.as_tag_mut() {
+                    let tag = t.name().as_utf8_str().as_ref().to_owned();
+                    if config.strip_scripts {
+                        strip_event_handlers(t)?;
+                    }
+                    *counts.entry(tag.clone()).or_insert(0) += 1;
+                    let attrs = asset_attrs(t, &config.lazy_attrs);
+                    if !attrs.is_empty() {
+                        for attr in attrs {
+                            if let Some(asset) = self.insecure_asset(t, attr, config) {
+                                eprintln!("MIXED: {} loads {}", self.url, asset);
+                                Stats::incr(&state.stats.mixed_content);
+                                mixed += 1;
+                            }
+                            if exporting {
+                                self.collect_attr(t, attr, &mut tasks, Task::new, config);
+                            } else if config.rewrite_assets {
+                                self.rewrite_asset(t, attr, &mut tasks, config)?;
+                            }
+                        }
+                    } else if tag == "iframe"
+                        && config.rewrite_srcdoc
+                        && config.rewrite_assets
+                        && !exporting
+                    {
+                        self.rewrite_srcdoc(t, &mut tasks, config, 0)?;
+                    } else if tag == "a" && (exporting || config.crawl) {
+                        self.collect_attr(t, "href", &mut tasks, Task::page, config);
+                        if config.convert_links && !exporting {
+                            self.convert_link(t, config)?;
+                        }
+                    } else if tag == "link" && exporting {
+                        // canonical, alternate, preload... are never downloaded but still listed
+                        self.collect_attr(t, "href", &mut tasks, Task::new, config);
+                    } else if tag == "meta"
+                        && (config.output_encoding.is_some() || config.declare_encoding)
+                    {
+                        let encoding = config.output_encoding.unwrap_or(UTF_8);
+                        declared |= set_meta_charset(t, encoding)?;
+                    }
+                    if let Some(new_base) = config.rewrite_base.as_ref().filter(|_| !exporting) {
+                        match tag.as_str() {
+                            "a" | "link" => self.rebase_url(t, "href", new_base)?,
+                            "img" | "script" => self.rebase_url(t, "src", new_base)?,
+                            _ => {}
+                        }
+                    }
+                };
+            }
+            if config.fail_on_mixed_content && mixed > 0 {
+                return Err(anyhow!(
+                    "{} loads {} assets over plain http",
+                    self.url,
+                    mixed
+                ));
+            }
+            if config.prefer_canonical_amp && metadata::is_amp(&self.url, &dom) {
+                let canonical = metadata::canonical(&dom)
+                    .and_then(|href| self.resolve(&href, config))
+                    .filter(|canonical| *canonical != self.url);
+                if let Some(canonical) = canonical {
+                    info!(
+                        "{} is an amp page, fetching {} instead",
+                        self.url, canonical
+                    );
+                    let mut task = Task::page(canonical);
+                    task.amp = Some(self.url.clone());
+                    return Ok(Filtered {
+                        body: vec![],
+                        tasks: vec![task],
+                        text: false,
+                        fetched: None,
+                        replaced: true,
+                    });
+                }
+            }
+            if config.links_csv.is_some() || config.check_links {
+                let found = links::collect(&dom, &self.url);
+                if config.check_links {
+                    self.check_links(&found, state).await;
+                }
+                if config.links_csv.is_some() {
+                    state.links.lock().unwrap().extend(found);
+                }
+            }
+            if comments_capped {
+                warn!(
+                    "{} has more than {} comments, only the first ones are kept",
+                    self.url, MAX_COMMENTS
+                );
+            }
+            let viewport = metadata::viewport(&dom);
+            if config.mobile_check && !viewport.as_deref().is_some_and(metadata::fits_device_width)
+            {
+                // shown whatever the verbosity, it's what the check is for
+                match &viewport {
+                    Some(viewport) => eprintln!(
+                        "MOBILE: {} has a viewport without width=device-width: {:?}",
+                        self.url, viewport
+                    ),
+                    None => eprintln!("MOBILE: {} has no viewport meta", self.url),
+                }
+            }
+            if config.show_metadata
+                || config.rewrite_assets
+                || config.count_words
+                || config.extract_comments
+            {
+                let decode = |field: String| {
+                    if config.decode_entities {
+                        html_escape::decode_html_entities(&field).into_owned()
+                    } else {
+                        field
+                    }
+                };
+                let metadata = Metadata {
+                    site: self.url.host_str().unwrap_or_default().to_owned(),
+                    num_links: *counts.get("a").unwrap_or(&0),
+                    images: *counts.get("img").unwrap_or(&0),
+                    last_fetch: chrono::Local::now().to_rfc2822(),
+                    http_version: http::version_name(version),
+                    amp_url: self.amp.as_ref().map(Url::to_string),
+                    title: metadata::title(&dom).map(decode),
+                    description: metadata::description(&dom).map(decode),
+                    encoding: charset.encoding.name(),
+                    encoding_confidence: charset.confidence,
+                    content_language,
+                    comments: config.extract_comments.then_some(comments),
+                    has_viewport_meta: viewport.is_some(),
+                    viewport: viewport.clone(),
+                    mobile_web_app_capable: metadata::mobile_web_app_capable(&dom),
+                    readability: config
+                        .count_words
+                        .then(|| Readability::of(&text::extract_text(&dom), body.len())),
+                };
+                if config.json {
+                    eprintln!("{}", serde_json::to_string(&metadata)?);
+                } else {
+                    eprintln!("{}", metadata.render());
+                }
+            }
+            let modified = (config.rewrite_assets && !exporting)
+                || config.output_encoding.is_some()
+                || config.declare_encoding
+                || config.rewrite_base.is_some()
+                || config.strip_comments
+                || config.strip_scripts;
+            if let Some(dir) = config.text_dir.as_ref().filter(|_| !exporting) {
+                let path = dir.join(text_name(&config.out_name(&self.url)));
+                if let Some(dir) = path.parent() {
+                    tokio::fs::create_dir_all(dir).await?;
+                }
+                tokio::fs::write(&path, text::extract_text(&dom)).await?;
+            } else if config.extract_text {
+                return Ok(Filtered {
+                    body: text::extract_text(&dom).into(),
+                    tasks,
+                    text: true,
+                    fetched: None,
+                    replaced: false,
+                });
+            }
+            let body = if config.body_only {
+                body_inner_html(&dom).unwrap_or_else(|| {
+                    warn!("no <body> in {}, saving whole document", self.url);
+                    dom.inner_html()
+                })
+            } else if let Some(layout) = config.layout {
+                format::serialize(&dom, layout)
+            } else if modified {
+                dom.inner_html()
+            } else {
+                drop(dom); // has to drop here as it 'borrows' the body
+                body
+            };
+            let body = if config.declare_encoding && !declared && !config.body_only {
+                insert_meta_charset(&body, config.output_encoding.unwrap_or(UTF_8))
+            } else {
+                body
+            };
+            let body = match (config.truncate_html, config.output_encoding) {
+                // cut in the encoding the page is saved in
+                (Some(max), encoding) => {
+                    let (truncated, complete) =
+                        encode_truncated(&body, encoding.unwrap_or(UTF_8), max);
+                    if !complete {
+                        info!("truncated {} to {} bytes", self.url, truncated.len());
+                    }
+                    truncated
+                }
+                // unmappable characters are written as numeric character references
+                (None, Some(encoding)) => encoding.encode(&body).0.into_owned(),
+                (None, None) => body.into(),
+            };
+            Ok(Filtered {
+                fetched: fetched.filter(|fetched| *fetched != body),
+                body,
+                tasks,
+                text: false,
+                replaced: false,
+            })
+        }
+    }
+
+    fn resolve(&self, href: &str, config: &Config) -> Option<Url> {
+        let base = self.base.as_ref().unwrap_or(&self.url);
+        match parse_url(Some(base), href, config.strict_urls) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Some(config.normalize(url)),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("ignoring invalid url {:?}: {}", href, e);
+                None
+            }
+        }
+    }
+
+    fn collect_attr(
+        &self,
+        t: &tl::HTMLTag,
+        attr: &str,
+        tasks: &mut Vec<Task>,
+        make: fn(Url) -> Task,
+        config: &Config,
+    ) {
+        if let Some(v) = t.attributes().get(attr).flatten() {
+            let v = v.as_utf8_str();
+            let srcs = match attr.ends_with("srcset") {
+                true => srcset_candidates(&v)
+                    .into_iter()
+                    .map(|(src, _)| src)
+                    .collect(),
+                false => vec![v.as_ref()],
+            };
+            for src in srcs {
+                if let Some(mut url) = self.resolve(src, config) {
+                    url.set_fragment(None);
+                    tasks.push(make(url));
+                }
+            }
+        }
+    }
+
+    // an http asset of an https page, which browsers block or warn about
+    fn insecure_asset(&self, t: &tl::HTMLTag, attr: &str, config: &Config) -> Option<Url> {
+        if self.url.scheme() != "https" {
+            return None;
+        }
+        let src = t.attributes().get(attr).flatten()?;
+        self.resolve(&src.as_utf8_str(), config)
+            .filter(|url| url.scheme() == "http")
+    }
+
+    fn rewrite_asset<'a>(
+        &self,
+        t: &mut tl::HTMLTag<'a>,
+        attr: &'a str,
+        tasks: &mut Vec<Task>,
+        config: &Config,
+    ) -> Result<()> {
+        if attr.ends_with("srcset") {
+            return self.rewrite_srcset(t, attr, tasks, config);
+        }
+        info!("Rewriting asset {:?}", t);
+        let attrs = t.attributes_mut();
+        if let Some(t) = attrs.get_mut(attr).flatten() {
+            let src = t.as_utf8_str();
+            if src.starts_with("data:") {
+                return Ok(());
+            }
+            let url = match self.resolve(&src, config) {
+                Some(url) => url,
+                None => return Ok(()),
+            };
+            let dst = relative_path(&config.out_name(&self.url), &config.out_name(&url));
+            info!("rewriting asset: {} => {}", src, dst);
+            t.set(dst)?;
+            tasks.push(Task::new(url));
+            // the local copy may not match the original's hash (or cors setup)
+            if !config.keep_integrity {
+                attrs.remove("integrity");
+                attrs.remove("crossorigin");
+            }
+        }
+        Ok(())
+    }
+
+    // The inline document of an <iframe srcdoc> gets its assets rewritten like
+    // the page's, its urls resolve against the page as they do in browsers
+    fn rewrite_srcdoc(
+        &self,
+        t: &mut tl::HTMLTag,
+        tasks: &mut Vec<Task>,
+        config: &Config,
+        depth: usize,
+    ) -> Result<()> {
+        if let Some(v) = t.attributes_mut().get_mut("srcdoc").flatten() {
+            if depth >= MAX_SRCDOC_DEPTH {
+                warn!(
+                    "{} nests srcdoc documents more than {} deep, leaving the rest as is",
+                    self.url, MAX_SRCDOC_DEPTH
+                );
+                return Ok(());
+            }
+            let html = html_escape::decode_html_entities(&v.as_utf8_str()).into_owned();
+            let mut dom = parse(&html, ParserOptions::default())?;
+            for n in dom.nodes_mut() {
+                if let Some(t) = n.as_tag_mut() {
+                    for attr in asset_attrs(t, &config.lazy_attrs) {
+                        self.rewrite_asset(t, attr, tasks, config)?;
+                    }
+                    if t.name().as_utf8_str() == "iframe" {
+                        self.rewrite_srcdoc(t, tasks, config, depth + 1)?;
+                    }
+                }
+            }
+            let rewritten = dom.inner_html();
+            debug!("rewriting srcdoc of {}: {:?}", self.url, rewritten);
+            v.set(html_escape::encode_double_quoted_attribute(&rewritten).into_owned())?;
+        }
+        Ok(())
+    }
+
+    // points a link to the local copy of its page, links off the page's host
+    // aren't crawled so they stay as they are
+    fn convert_link(&self, t: &mut tl::HTMLTag, config: &Config) -> Result<()> {
+        if let Some(href) = t.attributes_mut().get_mut("href").flatten() {
+            let url = match self.resolve(&href.as_utf8_str(), config) {
+                Some(url) if url.host_str() == self.url.host_str() => url,
+                _ => return Ok(()),
+            };
+            let mut dst = relative_path(&config.out_name(&self.url), &config.out_name(&url));
+            if let Some(fragment) = url.fragment() {
+                dst.push('#');
+                dst.push_str(fragment);
+            }
+            debug!("converting link: {} => {}", url, dst);
+            href.set(dst)?;
+        }
+        Ok(())
+    }
+
+    // `https://site/dir/page.html` => `<new_base>dir/page.html` for urls on the
+    // page's own site, relative ones keep working wherever the copy is hosted
+    fn rebase_url(&self, t: &mut tl::HTMLTag, attr: &'static str, new_base: &Url) -> Result<()> {
+        if let Some(v) = t.attributes_mut().get_mut(attr).flatten() {
+            let value = v.as_utf8_str();
+            if !value.contains("://") && !value.trim_start().starts_with("//") {
+                return Ok(());
+            }
+            let url = match Url::parse(value.trim()) {
+                Ok(url) => url,
+                // protocol relative
+                Err(_) => match self.url.join(value.trim()) {
+                    Ok(url) => url,
+                    Err(_) => return Ok(()),
+                },
+            };
+            if url.origin() != self.url.origin() {
+                return Ok(());
+            }
+            let mut rebased = new_base.join(url.path().trim_start_matches('/'))?;
+            rebased.set_query(url.query());
+            rebased.set_fragment(url.fragment());
+            debug!("rebasing url: {} => {}", url, rebased);
+            v.set(rebased.as_str())?;
+        }
+        Ok(())
+    }
+
+    // a lazy-loading srcset, each candidate url is downloaded and rewritten
+    fn rewrite_srcset<'a>(
+        &self,
+        t: &mut tl::HTMLTag<'a>,
+        attr: &'a str,
+        tasks: &mut Vec<Task>,
+        config: &Config,
+    ) -> Result<()> {
+        if let Some(v) = t.attributes_mut().get_mut(attr).flatten() {
+            let srcset = v.as_utf8_str().into_owned();
+            let rewritten: Vec<_> = srcset_candidates(&srcset)
+                .into_iter()
+                .map(|(src, descriptor)| {
+                    let url = match self.resolve(src, config) {
+                        Some(url) => url,
+                        None => return format!("{} {}", src, descriptor),
+                    };
+                    let dst = relative_path(&config.out_name(&self.url), &config.out_name(&url));
+                    info!("rewriting asset: {} => {}", src, dst);
+                    tasks.push(Task::new(url));
+                    format!("{} {}", dst, descriptor)
+                })
+                .collect();
+            v.set(
+                rewritten
+                    .iter()
+                    .map(|c| c.trim_end())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )?;
+        }
+        Ok(())
+    }
+
+    // reports the links of the page that lead to an error
+    async fn check_links(&self, found: &[links::Link], state: &State) {
+        let checks = found.iter().map(|link| async move {
+            let result = state.link_checker.check(&state.client, &link.target).await;
+            (link, result)
+        });
+        for (link, result) in futures::future::join_all(checks).await {
+            // for --links-csv, a target that was also fetched keeps the status it was fetched with
+            if let Ok(status) = result {
+                state
+                    .statuses
+                    .lock()
+                    .unwrap()
+                    .entry(link.target.clone())
+                    .or_insert(status);
+            }
+            let reason = match result {
+                Ok(status) if status < 400 => continue,
+                Ok(status) => status.to_string(),
+                Err(e) => e,
+            };
+            eprintln!("[BROKEN] {} -> {} ({})", self.url, link.target, reason);
+            Stats::incr(&state.stats.broken_links);
+        }
+    }
+
+    // only pages are followed, and only on hosts we were asked for
+    fn should_fetch(&self, config: &Config, seeds: &[Url]) -> bool {
+        if self.done {
+            return false;
+        }
+        if !self.page {
+            return !config.exporting();
+        }
+        // stands in for a page that was going to be saved
+        if self.amp.is_some() {
+            return true;
+        }
+        config.crawl
+            && seeds.iter().any(|seed| {
+                if config.no_parent {
+                    // the seed's directory, `/docs/intro.html` => `/docs/`
+                    let dir = seed.join(".").unwrap_or_else(|_| seed.clone());
+                    self.url.origin() == dir.origin() && self.url.path().starts_with(dir.path())
+                } else {
+                    hosts::same_site(&self.url, seed, config.include_subdomains)
+                }
+            })
+    }
+
+    async fn get(&self, config: &Config, state: &State) -> Result<Response> {
+        let client = &state.client;
+        let req = match &self.form {
+            Some(form) => client
+                .post(self.url.clone())
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(form.clone()),
+            None => client.get(self.url.clone()),
+        };
+        // set per request as the client's default headers are fixed
+        let req = match state.user_agents.next() {
+            Some(agent) => req.header(USER_AGENT, agent),
+            None => req,
+        };
+        let req = match &config.request_id_header {
+            Some(name) => {
+                let id = uuid::Uuid::new_v4().to_string();
+                info!("sending {}: {}", name, id);
+                req.header(name, id)
+            }
+            None => req,
+        };
+        // credentials in the url or an Authorization header win over the netrc
+        let login = config
+            .netrc
+            .as_ref()
+            .filter(|_| self.url.username().is_empty())
+            .filter(|_| !state.default_headers.contains_key(AUTHORIZATION))
+            .and_then(|netrc| netrc.credentials(self.url.host_str()?));
+        let req = match login {
+            Some(login) => req.basic_auth(&login.login, login.password.as_ref()),
+            None => req,
+        };
+        let req = req.build()?;
+        if config.http_trace {
+            trace!("> {} {}", req.method(), req.url());
+            http::trace_headers('>', &state.default_headers);
+            http::trace_headers('>', req.headers());
+        }
+        let har = config
+            .har_output
+            .is_some()
+            .then(|| (har::request(&req, &state.default_headers), Instant::now()));
+        let started = chrono::Local::now();
+        let resp = client.execute(req).await?;
+        if let Some((request, start)) = har {
+            let entry = har::entry(request, &resp, started, start.elapsed());
+            state.har.lock().unwrap().push(entry);
+        }
+        if config.links_csv.is_some() {
+            let status = resp.status().as_u16();
+            state
+                .statuses
+                .lock()
+                .unwrap()
+                .insert(self.url.clone(), status);
+        }
+        if config.http_trace {
+            trace!("< {:?} {}", resp.version(), resp.status());
+            http::trace_headers('<', resp.headers());
+        }
+        config
+            .header_limits
+            .check(resp.headers())
+            .map_err(|e| anyhow!("Error while fetching {} : {}", self.url, e))?;
+        if !resp.status().is_success() {
+            return Err(FetchError::Http {
+                url: self.url.clone(),
+                status: resp.status(),
+            }
+            .into());
+        }
+        Ok(resp)
+    }
+
+    // requests the url again while a body that should have content comes back empty
+    async fn fetch(&self, config: &Config, state: &State) -> Result<Response> {
+        if !config.retry_on_empty {
+            return self.request(config, state).await;
+        }
+        let mut backoff = EMPTY_RETRY_BACKOFF;
+        for attempt in 1.. {
+            let resp = self.request(config, state).await?;
+            let (status, version) = (resp.status(), resp.version());
+            let (url, headers) = (resp.url().clone(), resp.headers().clone());
+            let body = http::read_body(resp, config.stall_timeout).await?;
+            if !body.is_empty() || !http::expects_content(status, &headers) {
+                return Ok(http::rebuild(&url, status, version, headers, body));
+            }
+            if attempt > EMPTY_RETRIES {
+                break;
+            }
+            warn!(
+                "empty body from {}, retrying in {:?} ({}/{})",
+                self.url, backoff, attempt, EMPTY_RETRIES
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        Err(anyhow!("body still empty after {} retries", EMPTY_RETRIES))
+    }
+
+    // sends the request through the circuit breaker and flags slow responses
+    async fn request(&self, config: &Config, state: &State) -> Result<Response> {
+        let start = Instant::now();
+        let resp = match &config.breaker {
+            Some(breaker) => {
+                let host = self.url.host_str().unwrap_or_default();
+                state.hosts.wait_for_circuit(host).await;
+                let resp = self.get(config, state).await;
+                let ok = !resp.as_ref().is_err_and(is_host_failure);
+                state.hosts.record_outcome(host, ok, breaker);
+                resp
+            }
+            None => self.get(config, state).await,
+        };
+        // the response resolves once headers are in, which is close enough to ttfb
+        let ttfb = start.elapsed();
+        state.metrics.fetched(resp.is_ok(), ttfb);
+        let resp = resp?;
+        if config.max_response_time.is_some_and(|max| ttfb > max) {
+            eprintln!("SLOW: {} took {}ms", self.url, ttfb.as_millis());
+            Stats::incr(&state.stats.slow);
+        }
+        Ok(resp)
+    }
+
+    // hands the url back with the result so the frontier can drop it
+    async fn exec_tracked(
+        self,
+        config: &Config,
+        state: &State,
+    ) -> (Url, Result<Vec<Task>, FetchError>) {
+        let url = self.url.clone();
+        (url, self.exec(config, state).await)
+    }
+
+    async fn exec(self, config: &Config, state: &State) -> Result<Vec<Task>, FetchError> {
+        // every log line of the task carries its url and depth
+        let span = info_span!("fetch", url = %self.url, depth = self.depth);
+        let url = self.url.clone();
+        // a post isn't interchangeable with other requests to the same url
+        let joined = match self.form {
+            Some(_) => None,
+            None => Some(state.inflight.join(&url).await),
+        };
+        let result = match joined {
+            Some(Joined::Waited(outcome)) => {
+                info!("{} was fetched by a concurrent task", url);
+                outcome
+                    .unwrap_or_else(|| {
+                        Err(FetchError::Other {
+                            url: url.clone(),
+                            source: anyhow!("the concurrent fetch was abandoned"),
+                        })
+                    })
+                    .map(|()| vec![])
+            }
+            leader => {
+                let result = self
+                    .run(config, state)
+                    .instrument(span)
+                    .await
+                    .map_err(|e| FetchError::from_anyhow(url.clone(), e));
+                if let Some(Joined::Leader(leader)) = leader {
+                    leader.finish(match &result {
+                        Ok(_) => Ok(()),
+                        Err(e) => Err(e.duplicate()),
+                    });
+                }
+                result
+            }
+        };
+        if config.jsonl {
+            let written = state.written.lock().unwrap().remove(&url);
+            let error = result.as_ref().err().map(ToString::to_string);
+            match manifest::json_line(&url, written, error) {
+                Ok(line) => println!("{}", line),
+                // a line missing from the output beats stopping every other task
+                Err(e) => error!("Couldn't serialize the json line of {} : {}", url, e),
+            }
+        }
+        result
+    }
+
+    // Saves `body` to `out_name`, or links it to an identical file already saved
+    async fn write(
+        &self,
+        out_name: &Path,
+        body: &[u8],
+        config: &Config,
+        state: &State,
+    ) -> Result<()> {
+        // only the disk writes wait for it, not the transfer
+        let _write_slot = match &state.write_slots {
+            Some(slots) => Some(slots.acquire().await?),
+            None => None,
+        };
+        if let Some(dir) = out_name.parent().filter(|d| !d.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(FetchError::io(&self.url, dir))?;
+        }
+        let original = config
+            .dedup_link
+            .and_then(|mode| Some((mode, state.dedup.original(body, out_name)?)));
+        if let Some((mode, original)) = original {
+            info!("{:?} has the same content as {:?}", out_name, original);
+            dedup::link(&original, out_name, mode).await?;
+        } else {
+            let mut out_file = File::create(out_name)
+                .await
+                .map_err(FetchError::io(&self.url, out_name))?;
+            out_file
+                .write_all(body)
+                .await
+                .map_err(FetchError::io(&self.url, out_name))?;
+            // tokio finishes writes in the background otherwise, which
+            // could land after --done-file
+            out_file
+                .flush()
+                .await
+                .map_err(FetchError::io(&self.url, out_name))?;
+            if config.dedup_link.is_some() {
+                state.dedup.saved(body, out_name);
+            }
+        }
+        Ok(())
+    }
+
+    async fn run(mut self, config: &Config, state: &State) -> Result<Vec<Task>> {
+        let started = Instant::now();
+        if config.respect_robots && !state.robots.allows(&state.client, &self.url).await {
+            info!("{} is disallowed by robots.txt", self.url);
+            state.stats.robots_blocked(&self.url);
+            return Ok(vec![]);
+        }
+        if config.verify_strict
+            && config
+                .checksums
+                .as_ref()
+                .is_some_and(|checksums| !checksums.contains(&self.url))
+        {
+            info!("{} is not in the checksum list, skipping it", self.url);
+            return Ok(vec![]);
+        }
+        if config.probe_only {
+            let probe = probe::probe(&state.client, &self.url).await?;
+            state.probes.lock().unwrap().push(probe);
+            return Ok(vec![]);
+        }
+        let mut out_name = config.out_name(&self.url);
+        info!("Fetching {} => {:?}", self.url, out_name);
+        let _slot = match config.max_concurrent_hosts {
+            Some(limit) => {
+                let host = self.url.host_str().unwrap_or_default();
+                Some(state.hosts.acquire_slot(host, limit).await)
+            }
+            None => None,
+        };
+        if config.respect_robots {
+            if let Some(delay) = state.robots.crawl_delay(&state.client, &self.url).await {
+                let host = self.url.host_str().unwrap_or_default();
+                state.hosts.wait_turn(host, delay).await;
+            }
+        }
+        let _running = state.metrics.start_task();
+        let _timer = match config.max_time_per_host {
+            Some(budget) => {
+                let host = self.url.host_str().unwrap_or_default();
+                match state.hosts.start_timer(host, budget) {
+                    Some(timer) => Some(timer),
+                    None => {
+                        info!("{} used up its time budget, skipping {}", host, self.url);
+                        state.stats.host_abandoned(host);
+                        return Ok(vec![]);
+                    }
+                }
+            }
+            None => None,
+        };
+        let resp = match &config.replay_from {
+            Some(dir) => match replay::replay(&dir.join(&out_name)).await? {
+                Some(resp) => resp,
+                None => {
+                    warn!("{} is not in {:?}, fetching it", self.url, dir);
+                    self.fetch(config, state).await?
+                }
+            },
+            // assets don't need javascript, so they are still plain requests
+            None if config.headless && self.page => {
+                let selector = config.wait_for_selector.as_deref();
+                state.headless.render(&self.url, selector).await?
+            }
+            None => {
+                let resp = self.fetch(config, state).await?;
+                if config.base_on_final && *resp.url() != self.url {
+                    debug!("resolving links of {} against {}", self.url, resp.url());
+                    self.base = Some(resp.url().clone());
+                }
+                resp
+            }
+        };
+        if config.html_only && self.page {
+            let content_type = match http::type_override(&self.url, &config.type_overrides) {
+                Some(mime) => Some(mime.to_owned()),
+                None => header_str(&resp, CONTENT_TYPE),
+            };
+            if !content_type.as_deref().is_some_and(http::is_html) {
+                info!("{} is not html ({:?}), skipping it", self.url, content_type);
+                Stats::incr(&state.stats.skipped_non_html);
+                return Ok(vec![]);
+            }
+        }
+        let status = resp.status().as_u16();
+        let Filtered {
+            body,
+            mut tasks,
+            text,
+            fetched,
+            replaced,
+        } = if (self.page && config.parses_pages()) || config.exporting() {
+            self.filter_html(resp, config, state).await?
+        } else {
+            Filtered::raw(self.filter_noop(resp, config).await?)
+        };
+        state
+            .metrics
+            .received(fetched.as_ref().unwrap_or(&body).len());
+        if text {
+            out_name = text_name(&out_name);
+        }
+        // in export mode we only discover urls, nothing gets saved
+        if !config.exporting() && !replaced {
+            // checked before anything is written, so a bad download never hits the
+            // disk, and on what was downloaded rather than what rewriting made of it
+            if let Some(checksums) = &config.checksums {
+                checksums.verify(&self.url, fetched.as_ref().unwrap_or(&body))?;
+            }
+            let sha256 = config
+                .output_manifest
+                .is_some()
+                .then(|| checksums::sha256_hex(&body));
+            let change = config
+                .since
+                .as_ref()
+                .zip(sha256.as_deref())
+                .map(|(since, sha256)| since.compare(&self.url, sha256));
+            // the copy from the earlier run is left as is, unless it went missing
+            let unchanged = change == Some(Change::Unchanged)
+                && tokio::fs::try_exists(&out_name).await.unwrap_or(false);
+            let out_name = if unchanged {
+                info!("{} is unchanged, keeping {:?}", self.url, out_name);
+                Stats::incr(&state.stats.unchanged);
+                Some(out_name)
+            } else {
+                resolve_conflict(&out_name, config.on_path_conflict).await?
+            };
+            if let Some(out_name) = out_name {
+                if !unchanged {
+                    self.write(&out_name, &body, config, state).await?;
+                    match change {
+                        // shown whatever the verbosity, it's the changelog
+                        Some(Change::Added) => {
+                            eprintln!("ADDED: {}", self.url);
+                            Stats::incr(&state.stats.added);
+                        }
+                        Some(_) => {
+                            eprintln!("CHANGED: {}", self.url);
+                            Stats::incr(&state.stats.changed);
+                        }
+                        None => {}
+                    }
+                }
+                state.saved.lock().unwrap().push(manifest::Entry {
+                    url: self.url.clone(),
+                    path: out_name.clone(),
+                    page: self.page,
+                    size: body.len(),
+                    fetched: chrono::Local::now().to_rfc2822(),
+                    sha256,
+                    status,
+                    duration: started.elapsed(),
+                });
+                if config.jsonl {
+                    state
+                        .written
+                        .lock()
+                        .unwrap()
+                        .insert(self.url.clone(), (out_name.clone(), body.len()));
+                }
+                if let Some(fetched) = fetched.filter(|_| config.save_original) {
+                    let mut orig_name = out_name.into_os_string();
+                    orig_name.push(".orig");
+                    tokio::fs::write(&orig_name, fetched)
+                        .await
+                        .map_err(FetchError::io(&self.url, orig_name))?;
+                }
+            }
+        }
+        for task in &mut tasks {
+            task.parent = Some(self.url.clone());
+            task.depth = self.depth + 1;
+        }
+        if config.await_assets && self.page && !config.exporting() {
+            // the assets may be on other hosts, don't hold this one's slot meanwhile
+            drop(_slot);
+            drop(_timer);
+            self.fetch_assets(&mut tasks, config, state).await;
+        }
+        Ok(tasks)
+    }
+
+    // fetches the assets of the page as part of its own task, for --await-assets
+    async fn fetch_assets(&self, tasks: &mut [Task], config: &Config, state: &State) {
+        let mut assets = vec![];
+        for task in tasks.iter_mut().filter(|t| !t.page) {
+            // another page may have them already
+            if state.awaited.lock().unwrap().insert(task.url.clone()) {
+                assets.push(Task {
+                    parent: task.parent.clone(),
+                    depth: task.depth,
+                    ..Task::new(task.url.clone())
+                });
+            }
+            task.done = true;
+        }
+        if !assets.is_empty() {
+            info!("fetching {} assets of {}", assets.len(), self.url);
+        }
+        let fetches = assets
+            .into_iter()
+            .map(|asset| Box::pin(asset.exec(config, state)));
+        for result in futures::future::join_all(fetches).await {
+            if let Err(e) = result {
+                error!("{}", e);
+            }
+        }
+    }
+}
+
+// Lets every single-valued option default to a FETCH_<LONG_NAME> environment
+// variable, e.g. FETCH_USER_AGENT for --user-agent, so CI can pass credentials
+// without them showing up in the command line. `--help` lists the variables.
+fn with_env_defaults(command: Command<'static>) -> Command<'static> {
+    let options: Vec<_> = command
+        .get_arguments()
+        .filter(|arg| arg.is_takes_value_set() && arg.get_num_vals().is_none_or(|n| n == 1))
+        .filter_map(|arg| Some((arg.get_id(), arg.get_long()?)))
+        .collect();
+    options.into_iter().fold(command, |command, (id, long)| {
+        let var = format!("FETCH_{}", long.replace('-', "_").to_ascii_uppercase());
+        // clap wants the name for as long as the command lives, which is the whole run
+        command.mut_arg(id, |arg| {
+            arg.env(Box::leak(var.into_boxed_str()))
+                // their values may be secrets, keep them out of --help
+                .hide_env_values(true)
+        })
+    })
+}
+
+// The command line of rget, Config::from_matches reads what it parsed
+pub fn command() -> Command<'static> {
+    let command = Command::new(env!("CARGO_PKG_NAME"))
+        .version(env!("CARGO_PKG_VERSION"))
+        .trailing_var_arg(true)
+        .arg(
+            Arg::new("show_metadata")
+                .short('m')
+                .long("metadata")
+                .help("show metadata (section 2)"),
+        )
+        .arg(
+            Arg::new("rewrite_assets")
+                .short('r')
+                .long("rewrite")
+                .help("download and rewrite assets (section 3)"),
+        )
+        .arg(
+            Arg::new("save_original")
+                .long("save-original")
+                .help("also save pages changed by rewriting as fetched, to <file>.orig"),
+        )
+        .arg(
+            Arg::new("rewrite_query")
+                .long("rewrite-query")
+                .help("add a hash of the query string to file names, so ?v=1 and ?v=2 are saved apart"),
+        )
+        .arg(
+            Arg::new("await_assets")
+                .long("await-assets")
+                .visible_alias("parallel-asset-download-within-page")
+                .help("download the assets of a page together with it, so it is only done once they are"),
+        )
+        .arg(
+            Arg::new("verify_against")
+                .long("verify-against")
+                .visible_alias("content-hash-verify")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("fail downloads whose sha256 differs from the one listed in FILE (`sha256  url` lines)"),
+        )
+        .arg(
+            Arg::new("netrc")
+                .long("netrc")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("read per-host basic auth credentials from PATH instead of ~/.netrc"),
+        )
+        .arg(
+            Arg::new("no_netrc")
+                .long("no-netrc")
+                .conflicts_with("netrc")
+                .help("don't send credentials from ~/.netrc"),
+        )
+        .arg(
+            Arg::new("verify_strict")
+                .long("verify-strict")
+                .requires("verify_against")
+                .help("skip urls that aren't listed in the --verify-against file"),
+        )
+        .arg(
+            Arg::new("include_lazy_attrs")
+                .long("include-lazy-attrs")
+                .help("also download and rewrite the urls of lazy-loading attributes, see --lazy-attrs"),
+        )
+        .arg(
+            Arg::new("lazy_attrs")
+                .long("lazy-attrs")
+                .takes_value(true)
+                .value_name("ATTRS")
+                .default_value("data-src,data-srcset,data-lazy-src")
+                .help("comma separated lazy-loading attributes for --include-lazy-attrs, *srcset ones are srcset lists"),
+        )
+        .arg(
+            Arg::new("rewrite_srcdoc")
+                .long("rewrite-srcdoc")
+                .help("with -r, also rewrite the assets of documents inlined in <iframe srcdoc>"),
+        )
+        .arg(
+            Arg::new("keep_integrity")
+                .long("keep-integrity")
+                .help("keep integrity/crossorigin attributes on rewritten assets"),
+        )
+        .arg(
+            Arg::new("fail_on_mixed_content")
+                .long("fail-on-mixed-content")
+                .help("fail https pages that load assets over plain http"),
+        )
+        .arg(
+            Arg::new("pretty")
+                .long("pretty")
+                .conflicts_with("minify")
+                .help("indent saved pages, one element per line"),
+        )
+        .arg(
+            Arg::new("minify")
+                .long("minify")
+                .help("drop whitespace between tags and comments from saved pages"),
+        )
+        .arg(
+            Arg::new("prefer_canonical_amp")
+                .long("prefer-canonical-amp")
+                .visible_alias("canonicalize-amp")
+                .help("save the canonical version of amp pages instead"),
+        )
+        .arg(
+            Arg::new("crawl")
+                .short('c')
+                .long("crawl")
+                .help("follow links to other pages on the same host"),
+        )
+        .arg(
+            Arg::new("convert_links")
+                .long("convert-links")
+                .help("when crawling, point links between saved pages to the local copies"),
+        )
+        .arg(
+            Arg::new("mirror")
+                .long("mirror")
+                .help("mirror a site for offline browsing: --crawl --rewrite --convert-links --on-path-conflict skip"),
+        )
+        .arg(
+            Arg::new("export_urls")
+                .long("export-urls")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("write discovered urls to PATH instead of downloading them"),
+        )
+        .arg(
+            Arg::new("extract_links_only")
+                .long("extract-links-only")
+                .conflicts_with("export_urls")
+                .help("print the unique links found on pages to stdout, one per line, instead of downloading them"),
+        )
+        .arg(
+            Arg::new("null")
+                .short('0')
+                .long("null")
+                .visible_alias("output-null-terminated")
+                .conflicts_with("print_tree")
+                .help("end each url of --export-urls or --extract-links-only with NUL instead of a newline, like find -print0"),
+        )
+        .arg(
+            Arg::new("check_links")
+                .long("check-links")
+                .help("check every link of pages with HEAD and report those answering 4xx/5xx"),
+        )
+        .arg(
+            Arg::new("links_csv")
+                .long("links-csv")
+                .visible_alias("export-links-csv")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("write every link found on pages, with its text, rel and status, as csv to PATH"),
+        )
+        .arg(
+            Arg::new("har_output")
+                .long("har-output")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("record every request and response to PATH in HAR format"),
+        )
+        .arg(
+            Arg::new("write_index")
+                .long("write-index")
+                .visible_alias("write-index-html")
+                .help("write an index.html listing every saved page, with its url, size and fetch time"),
+        )
+        .arg(
+            Arg::new("output_manifest")
+                .long("output-manifest")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("write the file each url was saved to, or its failure, as json to PATH"),
+        )
+        .arg(
+            Arg::new("fetch_summary")
+                .long("fetch-summary")
+                .help("print each saved url with its status, size and time, the errors, and totals once done"),
+        )
+        .arg(
+            Arg::new("since_manifest")
+                .long("since-manifest")
+                .visible_alias("since-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("only rewrite files whose content changed since the --output-manifest at PATH, which is replaced unless --output-manifest is given, and list added, changed and removed urls"),
+        )
+        .arg(
+            Arg::new("done_file")
+                .long("done-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("create an empty file at PATH once everything has been written; if any url failed, exit with 1 instead"),
+        )
+        .arg(
+            Arg::new("print_tree")
+                .long("print-tree")
+                .help("print the tree of discovered links after the run"),
+        )
+        .arg(
+            Arg::new("graph_dot")
+                .long("graph-dot")
+                .visible_alias("dot-output")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("write the link graph to PATH in graphviz dot format"),
+        )
+        .arg(
+            Arg::new("error_rate_threshold")
+                .long("error-rate-threshold")
+                .takes_value(true)
+                .value_name("RATE")
+                .help("stop requesting a host for a while once its error rate exceeds RATE (0-1)"),
+        )
+        .arg(
+            Arg::new("breaker_cooldown")
+                .long("breaker-cooldown")
+                .takes_value(true)
+                .value_name("SECS")
+                .default_value("30")
+                .help("how long a host is left alone after its error rate was exceeded"),
+        )
+        .arg(
+            Arg::new("on_path_conflict")
+                .long("on-path-conflict")
+                .takes_value(true)
+                .possible_values(["skip", "rename", "error"])
+                .default_value("rename")
+                .help("what to do when an output path clashes with an existing file or directory"),
+        )
+        .arg(
+            Arg::new("name_scheme")
+                .long("name-scheme")
+                .takes_value(true)
+                .possible_values(["flat", "hash"])
+                .default_value("flat")
+                .help("name files after their url's host and path, or its sha256 (listed in hash-names.tsv)"),
+        )
+        .arg(
+            Arg::new("output_template")
+                .long("output-template")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .help("output path template, e.g. {date}/{host}/{path} (also {name}, {time}, {runid})"),
+        )
+        .arg(
+            Arg::new("template_var")
+                .long("template-var")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("KEY=VALUE")
+                .help("define a custom variable for --output-template"),
+        )
+        .arg(
+            Arg::new("content_type_for")
+                .long("content-type-for")
+                .visible_alias("content-type-override")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("EXT=MIME")
+                .help("treat urls ending in .EXT as MIME whatever their content-type header says"),
+        )
+        .arg(
+            Arg::new("output_encoding")
+                .long("output-encoding")
+                .takes_value(true)
+                .value_name("ENCODING")
+                .help("save html pages in ENCODING (e.g. iso-8859-1) instead of utf-8"),
+        )
+        .arg(
+            Arg::new("declare_encoding")
+                .long("output-encoding-declaration")
+                .help("make saved pages declare the charset they are saved in, adding a <meta charset> if they have none"),
+        )
+        .arg(
+            Arg::new("assume_charset")
+                .long("assume-charset")
+                .takes_value(true)
+                .value_name("NAME")
+                .help("read pages that don't declare a charset as NAME instead of detecting it"),
+        )
+        .arg(
+            Arg::new("detect_confidence")
+                .long("detect-encoding-confidence")
+                .takes_value(true)
+                .value_name("CONFIDENCE")
+                .default_value("0.5")
+                .help("read undeclared charsets as utf-8 unless detection is at least this sure (0-1)"),
+        )
+        .arg(
+            Arg::new("body_only")
+                .long("body-only")
+                .help("only save the contents of the <body> of html pages"),
+        )
+        .arg(
+            Arg::new("strip_comments")
+                .long("strip-comments")
+                .help("remove html comments from saved pages"),
+        )
+        .arg(
+            Arg::new("strip_cdata")
+                .long("strip-cdata")
+                .help("remove CDATA sections from saved pages"),
+        )
+        .arg(
+            Arg::new("strip_scripts")
+                .long("strip-scripts")
+                .help("remove <script> elements and on* event handlers from saved pages"),
+        )
+        .arg(
+            Arg::new("extract_text")
+                .long("extract-text")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .value_name("DIR")
+                .help("save the visible text of html pages as .txt instead of the html, or into DIR as well"),
+        )
+        .arg(
+            Arg::new("count_words")
+                .long("count-words")
+                .help("add word count, reading time and text/html ratio to the metadata"),
+        )
+        .arg(
+            Arg::new("mobile_check")
+                .long("mobile-check")
+                .help("report pages whose viewport meta doesn't set width=device-width"),
+        )
+        .arg(
+            Arg::new("extract_comments")
+                .long("extract-comments")
+                .help("add the html comments of pages to the metadata"),
+        )
+        .arg(
+            Arg::new("max_response_time")
+                .long("max-response-time-ms")
+                .takes_value(true)
+                .value_name("N")
+                .help("report urls taking longer than N milliseconds to respond as slow"),
+        )
+        .arg(
+            Arg::new("stall_timeout")
+                .long("timeout-per-byte")
+                .takes_value(true)
+                .value_name("N_MS")
+                .help("give up on a download when no data arrives for N_MS milliseconds"),
+        )
+        .arg(
+            Arg::new("retry_on_empty")
+                .long("retry-on-empty")
+                .visible_alias("retry-on-empty-body")
+                .help("ask again when a page, image, script or stylesheet comes back empty"),
+        )
+        .arg(
+            Arg::new("max_header_size")
+                .long("max-response-header-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("fail responses whose headers add up to more than BYTES"),
+        )
+        .arg(
+            Arg::new("max_header_count")
+                .long("max-response-headers")
+                .takes_value(true)
+                .value_name("N")
+                .help("fail responses with more than N headers (at most 100 are accepted anyway)"),
+        )
+        .arg(
+            Arg::new("accept_language")
+                .long("accept-language")
+                .takes_value(true)
+                .value_name("LANG")
+                .help("ask servers for content in LANG, e.g. \"fr-FR,fr;q=0.9\""),
+        )
+        .arg(
+            Arg::new("accept")
+                .long("accept")
+                .takes_value(true)
+                .value_name("TYPES")
+                .help("media types to ask servers for, sent as the Accept header"),
+        )
+        .arg(
+            Arg::new("brotli")
+                .long("brotli")
+                .help("ask for brotli/gzip/deflate compressed responses and decompress them"),
+        )
+        .arg(
+            Arg::new("user_agent")
+                .long("user-agent")
+                .short('U')
+                .takes_value(true)
+                .value_name("UA")
+                .help("identify as UA"),
+        )
+        .arg(
+            Arg::new("user_agent_file")
+                .long("user-agent-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("take turns with the user agents in PATH, one per line; overrides --user-agent"),
+        )
+        .arg(
+            Arg::new("headless")
+                .long("headless")
+                .help("load pages in a headless chromium and save them as rendered (needs the headless feature)"),
+        )
+        .arg(
+            Arg::new("wait_for_selector")
+                .long("wait-for-selector")
+                .takes_value(true)
+                .value_name("SELECTOR")
+                .help("with --headless, wait for SELECTOR to match before saving a page; implies --headless"),
+        )
+        .arg(
+            Arg::new("http3")
+                .long("http3")
+                .help("talk http/3 to servers (needs the http3 feature)"),
+        )
+        .arg(
+            Arg::new("simulate_browser")
+                .long("simulate-browser")
+                .takes_value(true)
+                .value_name("BROWSER")
+                .possible_values(["chrome", "firefox", "safari"])
+                .help("send the headers BROWSER sends when opening a page: User-Agent, Accept, Accept-Language, Accept-Encoding, DNT, Sec-Fetch-*, and Upgrade-Insecure-Requests and Sec-CH-UA* where it sends them; --header overrides them"),
+        )
+        .arg(
+            Arg::new("browser")
+                .long("browser")
+                .visible_alias("fetch-as-browser")
+                .conflicts_with("simulate_browser")
+                .help("look like a browser to servers, same as --simulate-browser chrome"),
+        )
+        .arg(
+            Arg::new("header")
+                .short('H')
+                .long("header")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("NAME: VALUE")
+                .help("send an extra header with every request"),
+        )
+        .arg(
+            Arg::new("request_id_header")
+                .long("request-id-header")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .default_missing_value("X-Request-Id")
+                .value_name("NAME")
+                .help("send a new uuid with every request in header NAME, X-Request-Id by default"),
+        )
+        .arg(
+            Arg::new("header_file")
+                .long("header-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("read extra headers from PATH, one `Name: Value` per line"),
+        )
+        .arg(
+            Arg::new("header_env")
+                .long("header-env")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("NAME=ENV_VAR")
+                .help("send header NAME with its value read from environment variable ENV_VAR"),
+        )
+        .arg(
+            Arg::new("redirect_same_host_only")
+                .long("redirect-same-host-only")
+                .visible_alias("limit-redirects-to-same-host")
+                .help("fail urls that redirect to another host instead of following them"),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .takes_value(true)
+                .value_name("URL")
+                .help("send every request through the proxy at URL instead of the one from HTTP(S)_PROXY"),
+        )
+        .arg(
+            Arg::new("proxy_auth")
+                .long("proxy-auth")
+                .takes_value(true)
+                .value_name("USER:PASS")
+                .requires("proxy")
+                .help("authenticate to the --proxy with basic auth, target servers never see it"),
+        )
+        .arg(
+            Arg::new("doh")
+                .long("doh")
+                .visible_alias("dns-over-https")
+                .takes_value(true)
+                .value_name("URL")
+                .help("resolve host names with the dns over https endpoint at URL (needs the doh feature)"),
+        )
+        .arg(
+            Arg::new("no_verify_ssl_hostname")
+                .long("no-verify-ssl-hostname")
+                .help("accept certificates issued for another host, as long as they are otherwise valid"),
+        )
+        .arg(
+            Arg::new("strict_urls")
+                .long("strict-urls")
+                .visible_alias("strict-url-parse")
+                .help("skip urls with spaces or other characters browsers escape, instead of escaping them"),
+        )
+        .arg(
+            Arg::new("https_only_hosts")
+                .long("https-only-hosts")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .value_name("HOSTS")
+                .help("comma separated hosts to fetch over https even when linked over http, subdomains included"),
+        )
+        .arg(
+            Arg::new("html_only")
+                .long("html-only")
+                .visible_alias("skip-binary-in-html-mode")
+                .help("don't save urls given or crawled as pages that turn out not to be html, assets are still saved"),
+        )
+        .arg(
+            Arg::new("title_filter")
+                .long("title-filter")
+                .takes_value(true)
+                .value_name("PATTERN")
+                .help("only save pages whose <title> matches the regex PATTERN"),
+        )
+        .arg(
+            Arg::new("title_exclude")
+                .long("title-exclude")
+                .takes_value(true)
+                .value_name("PATTERN")
+                .help("don't save pages whose <title> matches the regex PATTERN"),
+        )
+        .arg(
+            Arg::new("rewrite_base")
+                .long("rewrite-base-url")
+                .takes_value(true)
+                .value_name("NEW_BASE")
+                .help("point absolute links and assets to the page's own site at the same paths under NEW_BASE"),
+        )
+        .arg(
+            Arg::new("base_on_final")
+                .long("base-on-final")
+                .visible_alias("resolve-relative-against-final-url")
+                .help("resolve relative links of redirected pages against the url they were redirected to, like browsers do"),
+        )
+        .arg(
+            Arg::new("no_parent")
+                .long("no-parent")
+                .help("when crawling, don't follow links above the seed url's directory"),
+        )
+        .arg(
+            Arg::new("include_subdomains")
+                .long("include-subdomains")
+                .conflicts_with("no_parent")
+                .help("when crawling, also follow links to other subdomains of the seed's domain"),
+        )
+        .arg(
+            Arg::new("max_concurrent_hosts")
+                .long("max-concurrent-hosts")
+                .takes_value(true)
+                .value_name("N")
+                .help("crawl at most N hosts at the same time"),
+        )
+        .arg(
+            Arg::new("parallel_writes")
+                .long("parallel-writes")
+                .takes_value(true)
+                .value_name("N")
+                .help("write at most N files at the same time, however many downloads are running"),
+        )
+        .arg(
+            Arg::new("max_parse_size")
+                .long("max-parse-size")
+                .takes_value(true)
+                .value_name("BYTES")
+                .help("save pages larger than BYTES as is, without parsing or rewriting them"),
+        )
+        .arg(
+            Arg::new("truncate_html")
+                .long("truncate-html")
+                .takes_value(true)
+                .value_name("N")
+                .help("only save the first N bytes of each html page, cut at a character boundary, after it was parsed"),
+        )
+        .arg(
+            Arg::new("max_queue_depth")
+                .long("max-queue-depth")
+                .takes_value(true)
+                .value_name("N")
+                .help("stop following new links while N tasks are queued or running"),
+        )
+        .arg(
+            Arg::new("max_time_per_host")
+                .long("max-time-per-host")
+                .visible_alias("max-crawl-time-per-host")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("stop fetching from a host once SECONDS were spent on its urls"),
+        )
+        .arg(
+            Arg::new("dedup_link")
+                .long("dedup-link")
+                .takes_value(true)
+                .possible_values(["hardlink", "symlink", "copy"])
+                .help("save byte-identical files once and link duplicates to it"),
+        )
+        .arg(
+            Arg::new("ignore_mime")
+                .long("ignore-mime")
+                .help("try to parse pages as html whatever their content-type says"),
+        )
+        .arg(
+            Arg::new("post")
+                .long("post")
+                .takes_value(true)
+                .number_of_values(2)
+                .multiple_occurrences(true)
+                .value_names(&["URL", "DATA"])
+                .help("post url-encoded form DATA to URL and save the resulting page"),
+        )
+        .arg(
+            Arg::new("probe_only")
+                .long("probe-only")
+                .help("only report status, type, length and last-modified of each url"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .help("print reports and metadata as json"),
+        )
+        .arg(
+            Arg::new("jsonl")
+                .long("jsonl")
+                .visible_alias("json-lines")
+                .conflicts_with_all(&["extract_links_only", "export_urls"])
+                .help("print a line of json to stdout as each url is done, with its status, file, size and error"),
+        )
+        .arg(
+            Arg::new("decode_entities")
+                .long("decode-entities")
+                .help("decode html entities in the title and description of the metadata"),
+        )
+        .arg(
+            Arg::new("respect_robots")
+                .long("respect-robots")
+                .help("skip urls disallowed by the site's robots.txt and wait its Crawl-delay between requests"),
+        )
+        .arg(
+            Arg::new("frontier_file")
+                .long("frontier-file")
+                .takes_value(true)
+                .value_name("PATH")
+                .help("save pending urls to PATH while crawling, and resume from it if it exists"),
+        )
+        .arg(
+            Arg::new("randomise_order")
+                .long("randomise-order")
+                .visible_alias("randomize-order")
+                .help("fetch the given urls, and the links found on each page, in random order"),
+        )
+        .arg(
+            Arg::new("graceful_shutdown")
+                .long("graceful-shutdown")
+                .help("on Ctrl-C, finish the downloads in progress and write the reports before exiting"),
+        )
+        .arg(
+            Arg::new("replay_from")
+                .long("replay-from")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("read pages from a previous download in DIR instead of the network"),
+        )
+        .arg(
+            Arg::new("http_trace")
+                .long("http-trace")
+                .help("log request and response headers, with credentials redacted"),
+        )
+        .arg(
+            Arg::new("metrics_port")
+                .long("metrics-port")
+                .takes_value(true)
+                .value_name("PORT")
+                .help("serve prometheus metrics of the run on PORT at /metrics"),
+        )
+        .arg(
+            Arg::new("metrics_addr")
+                .long("metrics-addr")
+                .takes_value(true)
+                .value_name("ADDR")
+                .default_value("127.0.0.1")
+                .help("address the --metrics-port server listens on, e.g. 0.0.0.0 for every interface"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("add more verbosity")
+                .max_occurrences(3),
+        )
+        .arg(Arg::new("urls").multiple_values(true));
+    with_env_defaults(command)
+}
+
+impl Config {
+    // Reads a command line as rget's own, the first item being the program name
+    pub fn from_args<I, T>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        Self::from_matches(&command().try_get_matches_from(args)?)
+    }
+
+    // Values that don't parse panic naming their option, like the command line
+    // always reported them
+    pub fn from_matches(args: &ArgMatches) -> Result<Self> {
+        #[cfg(not(feature = "doh"))]
+        if args.is_present("doh") {
+            return Err(anyhow!(
+                "rget was built without dns over https support, rebuild it with \
+                 cargo build --features doh"
+            ));
+        }
+        #[cfg(not(feature = "headless"))]
+        if args.is_present("headless") || args.is_present("wait_for_selector") {
+            return Err(anyhow!(
+                "rget was built without headless browser support, rebuild it with \
+                 cargo build --features headless"
+            ));
+        }
+        #[cfg(not(feature = "http3"))]
+        if args.is_present("http3") {
+            return Err(anyhow!(
+                "rget was built without http/3 support, rebuild it with \
+                 RUSTFLAGS=\"--cfg reqwest_unstable\" cargo build --features http3"
+            ));
+        }
+        let mut headers = HeaderMap::new();
+        let browser = if args.is_present("browser") {
+            Some(Browser::Chrome)
+        } else {
+            args.is_present("simulate_browser")
+                .then(|| args.value_of_t_or_exit::<Browser>("simulate_browser"))
+        };
+        for (name, value) in browser.map(Browser::headers).unwrap_or_default() {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        if let Some(agent) = args.value_of("user_agent") {
+            headers.insert(USER_AGENT, agent.parse().expect("invalid --user-agent"));
+        }
+        // negotiation headers first so explicit --header values win
+        if let Some(accept) = args.value_of("accept") {
+            headers.insert(ACCEPT, accept.parse().expect("invalid --accept"));
+        }
+        if let Some(lang) = args.value_of("accept_language") {
+            headers.insert(
+                ACCEPT_LANGUAGE,
+                lang.parse().expect("invalid --accept-language"),
+            );
+        }
+        if let Some(path) = args.value_of("header_file") {
+            headers.extend(http::read_header_file(path).expect("invalid --header-file"));
+        }
+        for header in args.values_of("header").unwrap_or_default() {
+            let (name, value) = http::parse_header(header).expect("invalid --header");
+            headers.insert(name, value);
+        }
+        for spec in args.values_of("header_env").unwrap_or_default() {
+            let (name, value) = http::header_from_env(spec).expect("invalid --header-env");
+            headers.insert(name, value);
+        }
+        // decompression is opt-in so bodies are saved as sent unless asked for,
+        // a simulated browser asks for compression so it has to be undone
+        let brotli = args.is_present("brotli") || browser.is_some();
+        if brotli {
+            headers
+                .entry(ACCEPT_ENCODING)
+                .or_insert(HeaderValue::from_static("br, gzip, deflate"));
+        }
+        let proxy = args.value_of("proxy").map(|url| {
+            let proxy = reqwest::Proxy::all(url).expect("invalid --proxy");
+            match args.value_of("proxy_auth") {
+                Some(auth) => {
+                    let (user, pass) = auth.split_once(':').unwrap_or((auth, ""));
+                    proxy.basic_auth(user, pass)
+                }
+                None => proxy,
+            }
+        });
+        let posts: Vec<_> = args.values_of("post").unwrap_or_default().collect();
+        let posts = posts
+            .chunks(2)
+            .map(|post| {
+                let url =
+                    parse_url(None, post[0], args.is_present("strict_urls")).expect("invalid url");
+                (url, post[1].to_owned())
+            })
+            .collect();
+        let mirror = args.is_present("mirror");
+        Ok(Self {
+            show_metadata: args.is_present("show_metadata"),
+            rewrite_assets: args.is_present("rewrite_assets") || mirror,
+            crawl: args.is_present("crawl") || mirror,
+            convert_links: args.is_present("convert_links") || mirror,
+            export_urls: args.value_of("export_urls").map(PathBuf::from),
+            extract_links_only: args.is_present("extract_links_only"),
+            print_tree: args.is_present("print_tree"),
+            graph_dot: args.value_of("graph_dot").map(PathBuf::from),
+            breaker: args
+                .value_of("error_rate_threshold")
+                .map(|rate| BreakerConfig {
+                    threshold: rate.parse().expect("invalid error rate"),
+                    cooldown: Duration::from_secs(
+                        args.value_of_t("breaker_cooldown")
+                            .expect("invalid breaker cooldown"),
+                    ),
+                }),
+            // a mirror keeps what an earlier run saved, unless asked otherwise
+            on_path_conflict: match mirror && args.occurrences_of("on_path_conflict") == 0 {
+                true => PathConflict::Skip,
+                false => args.value_of_t_or_exit("on_path_conflict"),
+            },
+            output_template: args.value_of("output_template").map(|template| {
+                let vars = args
+                    .values_of("template_var")
+                    .unwrap_or_default()
+                    .map(template::parse_var)
+                    .collect::<Result<_>>()
+                    .expect("invalid template variable");
+                OutputTemplate::new(template, vars).expect("invalid output template")
+            }),
+            output_encoding: args
+                .value_of("output_encoding")
+                .map(|label| {
+                    Encoding::for_label(label.as_bytes()).expect("unknown output encoding")
+                })
+                // utf-16 can't be produced by the encoder, it falls back to utf-8
+                .map(Encoding::output_encoding),
+            declare_encoding: args.is_present("declare_encoding"),
+            body_only: args.is_present("body_only"),
+            strip_comments: args.is_present("strip_comments"),
+            strip_cdata: args.is_present("strip_cdata"),
+            strip_scripts: args.is_present("strip_scripts"),
+            extract_text: args.is_present("extract_text"),
+            text_dir: args.value_of("extract_text").map(PathBuf::from),
+            count_words: args.is_present("count_words"),
+            extract_comments: args.is_present("extract_comments"),
+            max_response_time: args.is_present("max_response_time").then(|| {
+                Duration::from_millis(
+                    args.value_of_t("max_response_time")
+                        .expect("invalid max response time"),
+                )
+            }),
+            no_parent: args.is_present("no_parent"),
+            include_subdomains: args.is_present("include_subdomains"),
+            max_time_per_host: args.is_present("max_time_per_host").then(|| {
+                Duration::from_secs_f64(
+                    args.value_of_t("max_time_per_host")
+                        .expect("invalid max time per host"),
+                )
+            }),
+            max_concurrent_hosts: args.is_present("max_concurrent_hosts").then(|| {
+                args.value_of_t("max_concurrent_hosts")
+                    .expect("invalid max concurrent hosts")
+            }),
+            dedup_link: args
+                .is_present("dedup_link")
+                .then(|| args.value_of_t_or_exit("dedup_link")),
+            ignore_mime: args.is_present("ignore_mime"),
+            probe_only: args.is_present("probe_only"),
+            json: args.is_present("json"),
+            jsonl: args.is_present("jsonl"),
+            keep_integrity: args.is_present("keep_integrity"),
+            rewrite_srcdoc: args.is_present("rewrite_srcdoc"),
+            replay_from: args.value_of("replay_from").map(PathBuf::from),
+            respect_robots: args.is_present("respect_robots"),
+            fail_on_mixed_content: args.is_present("fail_on_mixed_content"),
+            http_trace: args.is_present("http_trace"),
+            decode_entities: args.is_present("decode_entities"),
+            save_original: args.is_present("save_original"),
+            prefer_canonical_amp: args.is_present("prefer_canonical_amp"),
+            links_csv: args.value_of("links_csv").map(PathBuf::from),
+            retry_on_empty: args.is_present("retry_on_empty"),
+            har_output: args.value_of("har_output").map(PathBuf::from),
+            null_separated: args.is_present("null"),
+            headless: args.is_present("headless") || args.is_present("wait_for_selector"),
+            wait_for_selector: args.value_of("wait_for_selector").map(str::to_owned),
+            write_index: args.is_present("write_index"),
+            done_file: args.value_of("done_file").map(PathBuf::from),
+            rewrite_query: args.is_present("rewrite_query"),
+            check_links: args.is_present("check_links"),
+            await_assets: args.is_present("await_assets"),
+            checksums: args
+                .value_of("verify_against")
+                .map(|path| Checksums::from_file(path).expect("invalid --verify-against")),
+            verify_strict: args.is_present("verify_strict"),
+            netrc: if args.is_present("no_netrc") {
+                None
+            } else if let Some(path) = args.value_of("netrc") {
+                Some(Netrc::from_file(Path::new(path)).expect("invalid --netrc"))
+            } else {
+                Netrc::from_home().expect("invalid ~/.netrc")
+            },
+            output_manifest: args
+                .value_of("output_manifest")
+                .or_else(|| args.value_of("since_manifest"))
+                .map(PathBuf::from),
+            since: args.value_of("since_manifest").map(|path| {
+                manifest::Previous::from_file(Path::new(path)).expect("invalid --since-manifest")
+            }),
+            strict_urls: args.is_present("strict_urls"),
+            base_on_final: args.is_present("base_on_final"),
+            html_only: args.is_present("html_only"),
+            mobile_check: args.is_present("mobile_check"),
+            request_id_header: args
+                .value_of("request_id_header")
+                .map(|name| name.parse().expect("invalid --request-id-header")),
+            https_only_hosts: args
+                .values_of("https_only_hosts")
+                .unwrap_or_default()
+                .flat_map(|hosts| hosts.split(','))
+                .map(|host| host.trim().trim_end_matches('.').to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            title_filter: args
+                .value_of("title_filter")
+                .map(|re| Regex::new(re).expect("invalid --title-filter")),
+            title_exclude: args
+                .value_of("title_exclude")
+                .map(|re| Regex::new(re).expect("invalid --title-exclude")),
+            rewrite_base: args.value_of("rewrite_base").map(|base| {
+                // without a trailing slash joining would replace the last segment
+                let base = match base.ends_with('/') {
+                    true => base.to_owned(),
+                    false => format!("{}/", base),
+                };
+                Url::parse(&base).expect("invalid --rewrite-base-url")
+            }),
+            name_scheme: args.value_of_t_or_exit("name_scheme"),
+            lazy_attrs: match args.is_present("include_lazy_attrs") {
+                true => args
+                    .value_of("lazy_attrs")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|a| a.trim().to_ascii_lowercase())
+                    .filter(|a| !a.is_empty())
+                    .collect(),
+                false => vec![],
+            },
+            max_parse_size: args.is_present("max_parse_size").then(|| {
+                args.value_of_t("max_parse_size")
+                    .expect("invalid max parse size")
+            }),
+            truncate_html: args.is_present("truncate_html").then(|| {
+                args.value_of_t("truncate_html")
+                    .expect("invalid --truncate-html")
+            }),
+            max_queue_depth: args.is_present("max_queue_depth").then(|| {
+                args.value_of_t("max_queue_depth")
+                    .expect("invalid max queue depth")
+            }),
+            metrics_port: args.is_present("metrics_port").then(|| {
+                args.value_of_t("metrics_port")
+                    .expect("invalid metrics port")
+            }),
+            metrics_addr: args
+                .value_of_t("metrics_addr")
+                .expect("invalid metrics address"),
+            type_overrides: args
+                .values_of("content_type_for")
+                .unwrap_or_default()
+                .map(http::parse_type_override)
+                .collect::<Result<_>>()
+                .expect("invalid --content-type-for"),
+            charset_fallback: CharsetFallback {
+                assume: args
+                    .value_of("assume_charset")
+                    .map(|label| Encoding::for_label(label.as_bytes()).expect("unknown charset")),
+                min_confidence: args
+                    .value_of_t("detect_confidence")
+                    .expect("invalid detection confidence"),
+            },
+            stall_timeout: args.is_present("stall_timeout").then(|| {
+                Duration::from_millis(
+                    args.value_of_t("stall_timeout")
+                        .expect("invalid timeout per byte"),
+                )
+            }),
+            header_limits: HeaderLimits {
+                max_size: args.is_present("max_header_size").then(|| {
+                    args.value_of_t("max_header_size")
+                        .expect("invalid max response header size")
+                }),
+                max_count: args.is_present("max_header_count").then(|| {
+                    args.value_of_t("max_header_count")
+                        .expect("invalid max response headers")
+                }),
+            },
+            layout: if args.is_present("pretty") {
+                Some(Layout::Pretty)
+            } else if args.is_present("minify") {
+                Some(Layout::Minify)
+            } else {
+                None
+            },
+            headers,
+            brotli,
+            redirect_same_host_only: args.is_present("redirect_same_host_only"),
+            proxy,
+            no_verify_ssl_hostname: args.is_present("no_verify_ssl_hostname"),
+            http3: args.is_present("http3"),
+            doh: args.value_of("doh").map(str::to_owned),
+            user_agent_file: args.value_of("user_agent_file").map(str::to_owned),
+            parallel_writes: args.is_present("parallel_writes").then(|| {
+                args.value_of_t("parallel_writes")
+                    .expect("invalid parallel writes")
+            }),
+            randomise_order: args.is_present("randomise_order"),
+            graceful_shutdown: args.is_present("graceful_shutdown"),
+            frontier_file: args.value_of("frontier_file").map(PathBuf::from),
+            posts,
+        })
+    }
+}
+
+// The client every request of a run goes through
+fn build_client(config: &Config) -> Result<Client> {
+    let client = Client::builder()
+        .default_headers(config.headers.clone())
+        .brotli(config.brotli)
+        .gzip(config.brotli)
+        .deflate(config.brotli);
+    let client = if config.redirect_same_host_only {
+        client.redirect(http::same_host_redirects())
+    } else {
+        client
+    };
+    let client = match &config.proxy {
+        Some(proxy) => client.proxy(proxy.clone()),
+        None => client,
+    };
+    let client = if config.no_verify_ssl_hostname {
+        client.use_preconfigured_tls(tls::any_hostname_config())
+    } else {
+        client
+    };
+    #[cfg(feature = "http3")]
+    let client = if config.http3 {
+        client.http3_prior_knowledge()
+    } else {
+        client
+    };
+    #[cfg(feature = "doh")]
+    let client = match &config.doh {
+        Some(endpoint) => client.dns_resolver(Arc::new(
+            doh::DohResolver::new(endpoint).map_err(|e| e.context("invalid --doh"))?,
+        )),
+        None => client,
+    };
+    Ok(client.build()?)
+}
+
+// Fetches `seeds` and everything they lead to as `config` asks, writing the
+// files it asks for along the way
+pub async fn fetch_all(seeds: Vec<Url>, config: Config) -> Result<FetchSummary> {
+    let mut tasks = vec![];
+    for url in seeds {
+        tasks.push(Task::page(config.normalize(url)));
+    }
+    for (url, form) in &config.posts {
+        let mut task = Task::page(config.normalize(url.clone()));
+        task.form = Some(form.clone());
+        tasks.push(task);
+    }
+    let seeds: Vec<Url> = tasks.iter().map(|t| t.url.clone()).collect();
+    let frontier_file = &config.frontier_file;
+    let mut visited = HashSet::new();
+    if let Some(path) = frontier_file {
+        match frontier::load(path).await {
+            Ok(Some(frontier)) => {
+                info!(
+                    "resuming from {:?}, {} urls pending",
+                    path,
+                    frontier.pending.len()
+                );
+                visited.extend(frontier.visited.iter().filter_map(|u| Url::parse(u).ok()));
+                tasks.retain(|t| !visited.contains(&t.url));
+                for pending in frontier.pending {
+                    match Task::resume(pending) {
+                        Ok(task) => tasks.push(task),
+                        Err(e) => warn!("dropping pending task from {:?} : {}", path, e),
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    visited.extend(tasks.iter().map(|t| t.url.clone()));
+    let mut exported = vec![];
+    let mut graph = LinkGraph::default();
+    let user_agents = match &config.user_agent_file {
+        Some(path) => UserAgents::from_file(path)?,
+        None => UserAgents::default(),
+    };
+    let state = State {
+        client: build_client(&config)?,
+        default_headers: config.headers.clone(),
+        user_agents,
+        write_slots: config.parallel_writes.map(Semaphore::new),
+        ..Default::default()
+    };
+    // requests start in the order their tasks are first polled
+    let randomise = config.randomise_order;
+    if randomise {
+        tasks.shuffle(&mut rand::thread_rng());
+    }
+    let mut futures = FuturesUnordered::new();
+    let mut pending = HashMap::new();
+    for task in tasks {
+        if config.graphing() {
+            graph.add_root(task.url.clone());
+        }
+        pending.insert(task.url.clone(), task.pending());
+        futures.push(task.exec_tracked(&config, &state));
+    }
+    state.metrics.set_tasks(pending.len());
+    let metrics_server = config.metrics_port.map(|port| {
+        let addr = SocketAddr::new(config.metrics_addr, port);
+        MetricsServer::start(state.metrics.clone(), addr).expect("can't serve metrics")
+    });
+    let run_started = Instant::now();
+    let mut frontier_saved = Instant::now();
+    let mut failed = vec![];
+    let mut errors = vec![];
+    let ctrl_c = async {
+        if config.graceful_shutdown {
+            tokio::signal::ctrl_c().await.ok();
+        } else {
+            // leave Ctrl-C to the default handler
+            futures::future::pending::<()>().await;
+        }
+    };
+    tokio::pin!(ctrl_c);
+    let mut interrupted = false;
+    loop {
+        let (url, res) = tokio::select! {
+            next = futures.next() => match next {
+                Some(next) => next,
+                None => break,
+            },
+            _ = &mut ctrl_c, if !interrupted => {
+                interrupted = true;
+                // shown whatever the verbosity, the user is waiting for it
+                eprintln!(
+                    "INTERRUPTED: finishing {} tasks in progress, Ctrl-C again to quit now",
+                    futures.len()
+                );
+                tokio::spawn(async {
+                    tokio::signal::ctrl_c().await.ok();
+                    std::process::exit(130);
+                });
+                continue;
+            }
+        };
+        pending.remove(&url);
+        if res.is_err() {
+            failed.push(url);
+        }
+        match res {
+            Ok(mut sub_tasks) => {
+                if randomise {
+                    sub_tasks.shuffle(&mut rand::thread_rng());
+                }
+                for task in sub_tasks {
+                    if let Some(parent) = task.parent.as_ref().filter(|_| config.graphing()) {
+                        graph.add_edge(parent.clone(), task.url.clone());
+                    }
+                    if !visited.insert(task.url.clone()) {
+                        continue;
+                    }
+                    if config.exporting() {
+                        exported.push(task.url.to_string());
+                    }
+                    if !task.should_fetch(&config, &seeds) {
+                        continue;
+                    }
+                    if config
+                        .max_queue_depth
+                        .is_some_and(|max| pending.len() >= max)
+                    {
+                        if state.stats.queue_capped.load(Ordering::Relaxed) == 0 {
+                            // shown whatever the verbosity, the crawl is being cut short
+                            eprintln!(
+                                "CAPPED: {} tasks queued, not following any more links",
+                                pending.len()
+                            );
+                        }
+                        Stats::incr(&state.stats.queue_capped);
+                        continue;
+                    }
+                    pending.insert(task.url.clone(), task.pending());
+                    // kept pending for the frontier file, but not started
+                    if !interrupted {
+                        futures.push(task.exec_tracked(&config, &state));
+                    }
+                }
+            }
+            Err(e) => {
+                match &e {
+                    FetchError::Io { url, path, source } => {
+                        error!("Couldn't save {} : {:?} : {}", url, path, source)
+                    }
+                    FetchError::Parse { url, source } => {
+                        error!("Couldn't parse {} as html : {}", url, source)
+                    }
+                    FetchError::Http { .. } | FetchError::Other { .. } => error!("{}", e),
+                }
+                errors.push(e);
+            }
+        }
+        state.metrics.set_tasks(pending.len());
+        if let Some(path) = frontier_file
+            .as_ref()
+            .filter(|_| frontier_saved.elapsed() >= FRONTIER_SAVE_INTERVAL)
+        {
+            let frontier = Frontier {
+                visited: visited.iter().map(Url::to_string).collect(),
+                pending: pending.values().cloned().collect(),
+            };
+            if let Err(e) = frontier::save(path, &frontier).await {
+                error!("Error while writing {:?} : {}", path, e);
+            }
+            frontier_saved = Instant::now();
+        }
+    }
+    if let Some(server) = metrics_server {
+        server.stop().await;
+    }
+    if let Some(path) = &frontier_file {
+        if interrupted {
+            // so that the next run picks up where this one was stopped
+            let frontier = Frontier {
+                visited: visited.iter().map(Url::to_string).collect(),
+                pending: pending.values().cloned().collect(),
+            };
+            if let Err(e) = frontier::save(path, &frontier).await {
+                error!("Error while writing {:?} : {}", path, e);
+            }
+        } else if let Err(e) = tokio::fs::remove_file(path).await {
+            // a finished crawl has nothing to resume
+            debug!("couldn't remove {:?} : {}", path, e);
+        }
+    }
+    if config.exporting() {
+        let separator = if config.null_separated { "\0" } else { "\n" };
+        exported.push(String::new());
+        let exported = exported.join(separator);
+        match &config.export_urls {
+            Some(path) => {
+                if let Err(e) = tokio::fs::write(path, exported).await {
+                    error!("Error while writing {:?} : {}", path, e);
+                }
+            }
+            None => print!("{}", exported),
+        }
+    }
+    if config.probe_only {
+        let probes = state.probes.lock().unwrap();
+        if config.json {
+            println!("{}", serde_json::to_string_pretty(&*probes).unwrap());
+        } else {
+            print!("{}", probe::render_table(&probes));
+        }
+    }
+    if config.max_response_time.is_some()
+        || config.respect_robots
+        || config.fail_on_mixed_content
+        || config.max_time_per_host.is_some()
+        || config.check_links
+        || config.max_queue_depth.is_some()
+        || config.html_only
+    {
+        eprintln!("{}", state.stats.summary());
+    }
+    if config.print_tree {
+        print!("{}", graph.render_tree());
+    }
+    if let Some(path) = &config.graph_dot {
+        if let Err(e) = tokio::fs::write(path, graph.to_dot()).await {
+            error!("Error while writing {:?} : {}", path, e);
+        }
+    }
+    if let Some(path) = &config.har_output {
+        let har = har::to_json(&state.har.lock().unwrap()).expect("failed to serialize har");
+        if let Err(e) = tokio::fs::write(path, har).await {
+            error!("Error while writing {:?} : {}", path, e);
+        }
+    }
+    if config.write_index {
+        let index = manifest::render(&state.saved.lock().unwrap());
+        if let Err(e) = tokio::fs::write(manifest::INDEX_NAME, index).await {
+            error!("Error while writing {} : {}", manifest::INDEX_NAME, e);
+        }
+    }
+    if config.name_scheme == NameScheme::Hash {
+        let names = manifest::to_tsv(&state.saved.lock().unwrap());
+        if let Err(e) = tokio::fs::write(manifest::HASH_NAMES, names).await {
+            error!("Error while writing {} : {}", manifest::HASH_NAMES, e);
+        }
+    }
+    if let Some(since) = config.since.as_ref().filter(|_| !interrupted) {
+        let saved = state.saved.lock().unwrap();
+        let mut removed = 0;
+        for url in since.removed(&saved) {
+            eprintln!("REMOVED: {}", url);
+            removed += 1;
+        }
+        eprintln!("{}", state.stats.changes(removed));
+    }
+    // a partial one would have the next --since-manifest run add everything again
+    let partial = interrupted && config.since.is_some();
+    if let Some(path) = config.output_manifest.as_ref().filter(|_| !partial) {
+        let json = manifest::to_json(&state.saved.lock().unwrap(), &failed)
+            .expect("failed to serialize manifest");
+        if let Err(e) = output::write_atomic(path, json).await {
+            error!("Error while writing {:?} : {}", path, e);
+        }
+    }
+    if let Some(path) = &config.links_csv {
+        let csv = links::to_csv(
+            &state.links.lock().unwrap(),
+            &state.statuses.lock().unwrap(),
+        );
+        if let Err(e) = tokio::fs::write(path, csv).await {
+            error!("Error while writing {:?} : {}", path, e);
+        }
+    }
+    // last, so whoever watches for it can pick up all of the output. An
+    // interrupted run isn't done, and a failed one has nothing to pick up
+    if let Some(path) = config.done_file.as_ref().filter(|_| !interrupted) {
+        if !failed.is_empty() {
+            eprintln!(
+                "FAILED: {} urls couldn't be fetched, not writing {:?}",
+                failed.len(),
+                path
+            );
+        } else if let Err(e) = tokio::fs::write(path, "").await {
+            error!("Error while writing {:?} : {}", path, e);
+        }
+    }
+    let fetched = state
+        .saved
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|entry| FetchedItem {
+            url: entry.url.clone(),
+            path: entry.path.clone(),
+            status: entry.status,
+            bytes: entry.size as u64,
+            duration: entry.duration,
+        })
+        .collect();
+    Ok(FetchSummary {
+        fetched,
+        errors,
+        robots_blocked: state.stats.robots_skipped.load(Ordering::Relaxed),
+        total_bytes: state.metrics.bytes_received() as u64,
+        elapsed: run_started.elapsed(),
+        interrupted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    #[test]
+    fn only_server_side_errors_count_against_a_host() {
+        let http = |status| {
+            anyhow::Error::from(FetchError::Http {
+                url: url("https://example.com/"),
+                status,
+            })
+        };
+        assert!(!is_host_failure(&http(StatusCode::NOT_FOUND)));
+        assert!(!is_host_failure(&http(StatusCode::FORBIDDEN)));
+        assert!(is_host_failure(&http(StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(is_host_failure(&http(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(is_host_failure(&http(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(is_host_failure(&anyhow!("connection refused")));
+    }
+
+    #[test]
+    fn long_names_are_truncated_and_unique() {
+        let long = "a".repeat(300);
+        let first = url(&format!("https://example.com/{}/one.html", long));
+        let second = url(&format!("https://example.com/{}/two.html", long));
+        let first_name = filename_for_url(&first, false);
+        let second_name = filename_for_url(&second, false);
+        assert!(first_name.len() <= MAX_FILENAME_LEN, "{}", first_name);
+        assert!(second_name.len() <= MAX_FILENAME_LEN, "{}", second_name);
+        assert_ne!(first_name, second_name);
+        assert!(first_name.ends_with(".html"));
+        // the same url always gets the same name
+        assert_eq!(first_name, filename_for_url(&first, false));
+    }
+
+    #[test]
+    fn truncation_keeps_characters_whole() {
+        let name = "é".repeat(150);
+        let short = truncate_filename(name, &url("https://example.com/x"));
+        assert!(short.len() <= MAX_FILENAME_LEN);
+    }
+
+    #[test]
+    fn short_names_are_kept() {
+        let name = filename_for_url(&url("https://example.com/dir/page.html"), false);
+        assert_eq!(name, "example.com_dir_page.html");
+    }
+
+    #[test]
+    fn queries_get_names_of_their_own() {
+        let v1 = url("https://example.com/style.css?v=1");
+        let v2 = url("https://example.com/style.css?v=2");
+        let v1_name = filename_for_url(&v1, true);
+        let v2_name = filename_for_url(&v2, true);
+        assert_ne!(v1_name, v2_name);
+        // the first 8 hex characters of the query's sha256
+        let hash = &hex(&Sha256::digest(b"v=1"))[..8];
+        assert_eq!(v1_name, format!("example.com_style_{}.css", hash));
+        // without --rewrite-query, the query is ignored
+        assert_eq!(filename_for_url(&v1, false), filename_for_url(&v2, false));
+        assert_eq!(
+            query_suffixed("example.com_download".to_owned(), "id=7"),
+            format!(
+                "example.com_download_{}",
+                &hex(&Sha256::digest(b"id=7"))[..8]
+            )
+        );
+    }
+
+    #[test]
+    fn hashed_names_are_stable_and_distinct() {
+        let page = url("https://example.com/a/page.HTML");
+        let name = hashed_filename(&page);
+        assert_eq!(name, hashed_filename(&page.clone()));
+        assert_eq!(
+            name,
+            format!("{}.html", hex(&Sha256::digest(page.as_str())))
+        );
+        let names: HashSet<_> = (0..1000)
+            .map(|i| hashed_filename(&url(&format!("https://example.com/{}", i))))
+            .collect();
+        assert_eq!(names.len(), 1000);
+        // no extension to keep, or nothing that looks like one
+        assert_eq!(hashed_filename(&url("https://example.com/")).len(), 64);
+        assert_eq!(
+            hashed_filename(&url("https://example.com/v1.2-beta")).len(),
+            64
+        );
+    }
+
+    #[test]
+    fn hrefs_are_fixed_up_like_browsers_do() {
+        let base = url("http://example.com/dir/page.html");
+        for (href, expected) in [
+            ("my page.html", "http://example.com/dir/my%20page.html"),
+            (" /trimmed.html ", "http://example.com/trimmed.html"),
+            ("a[1].png", "http://example.com/dir/a[1].png"),
+            ("?q=a b", "http://example.com/dir/page.html?q=a%20b"),
+            ("\\\\other.com\\x.html", "http://other.com/x.html"),
+        ] {
+            let parsed = parse_url(Some(&base), href, false).unwrap();
+            assert_eq!(parsed.as_str(), expected, "{:?}", href);
+            assert!(parse_url(Some(&base), href, true).is_err(), "{:?}", href);
+        }
+    }
+
+    #[test]
+    fn clean_hrefs_pass_strict_parsing() {
+        let base = url("http://example.com/dir/page.html");
+        for (href, expected) in [
+            ("other.html", "http://example.com/dir/other.html"),
+            (
+                "/a%20b.html?q=1#top",
+                "http://example.com/a%20b.html?q=1#top",
+            ),
+            ("//cdn.example.com/x.js", "http://cdn.example.com/x.js"),
+        ] {
+            let parsed = parse_url(Some(&base), href, true).unwrap();
+            assert_eq!(parsed.as_str(), expected);
+        }
+        assert!(parse_url(None, "http://[::1/", false).is_err());
+        assert!(parse_url(None, "relative.html", false).is_err());
+    }
+
+    #[test]
+    fn cdata_sections_are_removed() {
+        let cases = [
+            ("<p>a<![CDATA[ x < y ]]>b</p>", "<p>ab</p>"),
+            ("<![CDATA[1]]><i><![CDATA[2]]></i>", "<i></i>"),
+            // an unterminated section runs to the end
+            ("<p>a</p><![CDATA[ <p>b</p>", "<p>a</p>"),
+            ("<p>no cdata</p>", "<p>no cdata</p>"),
+        ];
+        for (html, expected) in cases {
+            assert_eq!(strip_cdata(html), expected);
+        }
+    }
+
+    #[test]
+    fn script_elements_are_removed() {
+        let cases = [
+            (
+                "<p>a</p><script>alert(1)</script><p>b</p>",
+                "<p>a</p><p>b</p>",
+            ),
+            ("<SCRIPT src=x.js></SCRIPT >", ""),
+            ("<script\ntype=module>x</script\n>", ""),
+            ("<script/>", ""),
+            // the rest of an unterminated script is dropped
+            ("<p>a</p><script>alert(1)", "<p>a</p>"),
+            // near misses are kept
+            ("<noscript><img></noscript>", "<noscript><img></noscript>"),
+            ("<scripts>x</scripts>", "<scripts>x</scripts>"),
+            ("<script-x>x</script-x>", "<script-x>x</script-x>"),
+        ];
+        for (html, expected) in cases {
+            assert_eq!(strip_scripts(html), expected, "{:?}", html);
+        }
+    }
+
+    #[test]
+    fn event_handlers_are_removed() {
+        let html = "<a href=\"/x\" onclick=\"go()\" ONMOUSEOVER=\"x()\" on=\"2\">x</a>";
+        let mut dom = parse(html, ParserOptions::default()).unwrap();
+        for n in dom.nodes_mut() {
+            if let Some(t) = n.as_tag_mut() {
+                strip_event_handlers(t).unwrap();
+            }
+        }
+        let out = dom.inner_html();
+        assert!(!out.to_ascii_lowercase().contains("onclick"), "{}", out);
+        assert!(!out.to_ascii_lowercase().contains("onmouseover"), "{}", out);
+        assert!(out.contains("href=\"/x\""), "{}", out);
+        // too short to name an event
+        assert!(out.contains("on=\"2\""), "{}", out);
+    }
+
+    #[test]
+    fn meta_charset_is_inserted_first_in_head() {
+        let cases = [
+            (
+                "<html><head><title>t</title></head></html>",
+                "<html><head><meta charset=\"UTF-8\"><title>t</title></head></html>",
+            ),
+            (
+                "<HTML lang=en><Head id=h><title>t</title></head></html>",
+                "<HTML lang=en><Head id=h><meta charset=\"UTF-8\"><title>t</title></head></html>",
+            ),
+            // not mistaken for <head>
+            (
+                "<html><body><header>x</header></body></html>",
+                "<html><meta charset=\"UTF-8\"><body><header>x</header></body></html>",
+            ),
+            (
+                "<!DOCTYPE html><p>x</p>",
+                "<!DOCTYPE html><meta charset=\"UTF-8\"><p>x</p>",
+            ),
+            ("<p>x</p>", "<meta charset=\"UTF-8\"><p>x</p>"),
+        ];
+        for (html, expected) in cases {
+            assert_eq!(insert_meta_charset(html, UTF_8), expected);
+        }
+    }
+
+    #[test]
+    fn declared_charsets_are_updated() {
+        let html = "<meta charset=\"iso-8859-1\"><meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-1\"><meta name=\"x\">";
+        let mut dom = parse(html, ParserOptions::default()).unwrap();
+        let declared: Vec<bool> = dom
+            .nodes_mut()
+            .iter_mut()
+            .filter_map(|n| n.as_tag_mut())
+            .map(|t| set_meta_charset(t, UTF_8).unwrap())
+            .collect();
+        assert_eq!(declared, [true, true, false]);
+        let out = dom.inner_html();
+        assert!(out.contains("<meta charset=\"UTF-8\">"), "{}", out);
+        assert!(
+            out.contains("content=\"text/html; charset=UTF-8\""),
+            "{}",
+            out
+        );
+    }
+
+    #[test]
+    fn truncation_cuts_whole_characters_of_the_output_encoding() {
+        use encoding_rs::{SHIFT_JIS, WINDOWS_1252};
+
+        // 1 + 2 * 3 bytes in utf-8
+        let (utf8, complete) = encode_truncated("aééé", UTF_8, 6);
+        assert_eq!(utf8, "aéé".as_bytes());
+        assert!(!complete);
+        // one byte each in windows-1252, `日` becomes `&#26085;`
+        let (latin, _) = encode_truncated("aé日b", WINDOWS_1252, 10);
+        assert_eq!(latin, b"a\xe9&#26085;");
+        let (latin, _) = encode_truncated("aé日b", WINDOWS_1252, 9);
+        assert_eq!(latin, b"a\xe9");
+        // two bytes each in shift_jis
+        let (sjis, _) = encode_truncated("日本語", SHIFT_JIS, 5);
+        assert_eq!(sjis, SHIFT_JIS.encode("日本").0.into_owned());
+        let (all, complete) = encode_truncated("short", UTF_8, 100);
+        assert_eq!(all, b"short");
+        assert!(complete);
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
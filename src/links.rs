@@ -0,0 +1,154 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use reqwest::{Client, Url};
+use tl::VDom;
+use tokio::sync::OnceCell;
+
+use crate::probe;
+
+// An <a> found on a page, for --links-csv
+pub struct Link {
+    pub source: Url,
+    pub target: Url,
+    pub text: String,
+    pub rel: String,
+}
+
+// Every <a href> of a page that resolves to an http(s) url
+pub fn collect(dom: &VDom, source: &Url) -> Vec<Link> {
+    let parser = dom.parser();
+    let base_url = Url::options().base_url(Some(source));
+    dom.nodes()
+        .iter()
+        .filter_map(|n| n.as_tag())
+        .filter(|t| t.name().as_utf8_str().eq_ignore_ascii_case("a"))
+        .filter_map(|t| {
+            let attrs = t.attributes();
+            let href = attrs.get("href").flatten()?.as_utf8_str();
+            let target = base_url.parse(&href).ok()?;
+            if !matches!(target.scheme(), "http" | "https") {
+                return None;
+            }
+            // inner_text goes through nested markup like <b> or <span>
+            let text = html_escape::decode_html_entities(&t.inner_text(parser))
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            let rel = attrs
+                .get("rel")
+                .flatten()
+                .map(|r| r.as_utf8_str().into_owned())
+                .unwrap_or_default();
+            Some(Link {
+                source: source.clone(),
+                target,
+                text,
+                rel,
+            })
+        })
+        .collect()
+}
+
+// the status a link target answered with, or why it didn't
+type Checked = Result<u16, String>;
+
+// Statuses of the link targets checked by --check-links, each one is only
+// requested once however many pages link to it
+#[derive(Default)]
+pub struct Checker {
+    checked: Mutex<HashMap<Url, Arc<OnceCell<Checked>>>>,
+}
+
+impl Checker {
+    // Status of `url` with HEAD, or a one byte GET where HEAD isn't allowed
+    pub async fn check(&self, client: &Client, url: &Url) -> Checked {
+        let mut url = url.clone();
+        url.set_fragment(None);
+        let cell = self
+            .checked
+            .lock()
+            .unwrap()
+            .entry(url.clone())
+            .or_default()
+            .clone();
+        cell.get_or_init(|| async {
+            probe::probe(client, &url)
+                .await
+                .map(|p| p.status)
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .clone()
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+// One row per link, status is empty for targets that weren't fetched
+pub fn to_csv(links: &[Link], statuses: &HashMap<Url, u16>) -> String {
+    let mut csv = String::from("source,target,text,rel,status\n");
+    for link in links {
+        let status = statuses
+            .get(&link.target)
+            .map(u16::to_string)
+            .unwrap_or_default();
+        let row = [
+            link.source.as_str(),
+            link.target.as_str(),
+            &link.text,
+            &link.rel,
+            &status,
+        ];
+        let row: Vec<_> = row.iter().map(|f| csv_field(f)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_are_quoted_when_needed() {
+        let cases = [
+            ("plain", "plain"),
+            ("", ""),
+            ("a, b", "\"a, b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("two\nlines", "\"two\nlines\""),
+            ("cr\rlf", "\"cr\rlf\""),
+        ];
+        for (field, expected) in cases {
+            assert_eq!(csv_field(field), expected);
+        }
+    }
+
+    #[test]
+    fn links_with_nested_markup_and_statuses() {
+        let source = Url::parse("https://example.com/dir/").unwrap();
+        let html = "<a href=\"a.html\" rel=\"nofollow noopener\">Say <b>\"hi\",</b>\n  there</a>\
+                    <a href=\"mailto:x@example.com\">mail</a>\
+                    <a href=\"https://other.example/\">&amp; more</a>";
+        let dom = tl::parse(html, Default::default()).unwrap();
+        let links = collect(&dom, &source);
+        assert_eq!(links.len(), 2);
+        let statuses = HashMap::from([(links[0].target.clone(), 404)]);
+        assert_eq!(
+            to_csv(&links, &statuses),
+            "source,target,text,rel,status\n\
+             https://example.com/dir/,https://example.com/dir/a.html,\"Say \"\"hi\"\", there\",nofollow noopener,404\n\
+             https://example.com/dir/,https://other.example/,& more,,\n"
+        );
+    }
+}
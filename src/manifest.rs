@@ -0,0 +1,342 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::output::relative_path;
+
+// where --write-index puts the page, at the root of the output
+pub const INDEX_NAME: &str = "index.html";
+
+// where --name-scheme hash lists which url each file is from
+pub const HASH_NAMES: &str = "hash-names.tsv";
+
+// A file saved by the run, for --write-index and --output-manifest
+pub struct Entry {
+    pub url: Url,
+    pub path: PathBuf,
+    pub page: bool,
+    pub size: usize,
+    pub fetched: String,
+    // of the saved content, for --output-manifest
+    pub sha256: Option<String>,
+    pub status: u16,
+    // from the start of the task to the file being written
+    pub duration: Duration,
+}
+
+// One url of --output-manifest
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    url: &'a str,
+    file: Option<String>,
+    status: &'static str,
+    bytes: usize,
+    sha256: Option<&'a str>,
+}
+
+// An entry of a manifest read back, older ones have no sha256
+#[derive(Deserialize)]
+struct PreviousEntry {
+    url: String,
+    status: String,
+    sha256: Option<String>,
+}
+
+// What became of a url since the manifest of --since-manifest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    Added,
+    Changed,
+    Unchanged,
+}
+
+// The urls an earlier run saved, with the sha256 of what it saved
+pub struct Previous {
+    digests: HashMap<Url, Option<String>>,
+}
+
+impl Previous {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let json =
+            std::fs::read_to_string(path).with_context(|| format!("can't read {:?}", path))?;
+        let entries: Vec<PreviousEntry> =
+            serde_json::from_str(&json).with_context(|| format!("invalid manifest {:?}", path))?;
+        let digests = entries
+            .into_iter()
+            .filter(|entry| entry.status == "ok")
+            .map(|entry| {
+                let url = Url::parse(&entry.url)
+                    .with_context(|| format!("invalid url {:?} in {:?}", entry.url, path))?;
+                Ok((url, entry.sha256))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { digests })
+    }
+
+    // Content without a digest to compare with counts as changed
+    pub fn compare(&self, url: &Url, sha256: &str) -> Change {
+        match self.digests.get(url) {
+            None => Change::Added,
+            Some(Some(previous)) if previous == sha256 => Change::Unchanged,
+            Some(_) => Change::Changed,
+        }
+    }
+
+    // Saved by the earlier run but not by this one
+    pub fn removed<'a>(&'a self, entries: &'a [Entry]) -> impl Iterator<Item = &'a Url> {
+        let saved: HashSet<&Url> = entries.iter().map(|entry| &entry.url).collect();
+        self.digests.keys().filter(move |url| !saved.contains(url))
+    }
+}
+
+// How a task ended, for --jsonl
+#[derive(Serialize)]
+struct Line<'a> {
+    url: &'a str,
+    // ok when saved, skipped when there was nothing to save
+    status: &'static str,
+    path: Option<String>,
+    bytes: usize,
+    error: Option<String>,
+}
+
+pub fn json_line(
+    url: &Url,
+    written: Option<(PathBuf, usize)>,
+    error: Option<String>,
+) -> serde_json::Result<String> {
+    let status = match (&written, &error) {
+        (_, Some(_)) => "error",
+        (Some(_), None) => "ok",
+        (None, None) => "skipped",
+    };
+    let (path, bytes) = match written {
+        Some((path, bytes)) => (Some(path.to_string_lossy().into_owned()), bytes),
+        None => (None, 0),
+    };
+    serde_json::to_string(&Line {
+        url: url.as_str(),
+        status,
+        path,
+        bytes,
+        error,
+    })
+}
+
+// Saved files then failed urls, as a json array
+pub fn to_json(entries: &[Entry], failed: &[Url]) -> serde_json::Result<String> {
+    let saved = entries.iter().map(|entry| ManifestEntry {
+        url: entry.url.as_str(),
+        file: Some(entry.path.to_string_lossy().into_owned()),
+        status: "ok",
+        bytes: entry.size,
+        sha256: entry.sha256.as_deref(),
+    });
+    let failed = failed.iter().map(|url| ManifestEntry {
+        url: url.as_str(),
+        file: None,
+        status: "error",
+        bytes: 0,
+        sha256: None,
+    });
+    serde_json::to_string_pretty(&saved.chain(failed).collect::<Vec<_>>())
+}
+
+// `path<TAB>url` lines, for files whose names don't tell their url
+pub fn to_tsv(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|entry| format!("{}\t{}\n", entry.path.display(), entry.url))
+        .collect()
+}
+
+// Characters of a local path that mean something else in an href
+fn href_escape(path: &str) -> String {
+    let mut href = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '%' => href.push_str("%25"),
+            '#' => href.push_str("%23"),
+            '?' => href.push_str("%3F"),
+            ' ' => href.push_str("%20"),
+            c => href.push(c),
+        }
+    }
+    href
+}
+
+// A table of the saved pages, in the order they were saved
+pub fn render(entries: &[Entry]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>rget index</title>\n</head>\n<body>\n<table>\n<tr><th>page</th><th>url</th><th>size</th><th>fetched</th></tr>\n",
+    );
+    for entry in entries.iter().filter(|e| e.page) {
+        let local = relative_path(Path::new(INDEX_NAME), &entry.path);
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">{}</a></td><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            encode_double_quoted_attribute(&href_escape(&local)),
+            encode_text(&local),
+            encode_double_quoted_attribute(entry.url.as_str()),
+            encode_text(entry.url.as_str()),
+            entry.size,
+            encode_text(&entry.fetched),
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn entry(u: &str) -> Entry {
+        Entry {
+            url: url(u),
+            path: PathBuf::from("a.html"),
+            page: true,
+            size: 4,
+            fetched: String::new(),
+            sha256: Some("abc".to_owned()),
+            status: 200,
+            duration: Duration::ZERO,
+        }
+    }
+
+    fn previous() -> Previous {
+        Previous {
+            digests: HashMap::from([
+                (url("https://example.com/same"), Some("abc".to_owned())),
+                (url("https://example.com/edited"), Some("abc".to_owned())),
+                // from a manifest written before digests were
+                (url("https://example.com/old"), None),
+                (url("https://example.com/gone"), Some("abc".to_owned())),
+            ]),
+        }
+    }
+
+    #[test]
+    fn compare_against_the_previous_digest() {
+        let previous = previous();
+        let compare = |u| previous.compare(&url(u), "abc");
+        assert_eq!(compare("https://example.com/same"), Change::Unchanged);
+        assert_eq!(
+            previous.compare(&url("https://example.com/edited"), "def"),
+            Change::Changed
+        );
+        assert_eq!(compare("https://example.com/old"), Change::Changed);
+        assert_eq!(compare("https://example.com/new"), Change::Added);
+    }
+
+    #[test]
+    fn removed_urls_are_the_ones_not_saved_again() {
+        let previous = previous();
+        let entries = [
+            entry("https://example.com/same"),
+            entry("https://example.com/edited"),
+            entry("https://example.com/old"),
+            entry("https://example.com/new"),
+        ];
+        let removed: Vec<_> = previous.removed(&entries).collect();
+        assert_eq!(removed, [&url("https://example.com/gone")]);
+        assert_eq!(previous.removed(&[]).count(), 4);
+    }
+
+    #[test]
+    fn manifest_lists_saved_then_failed() {
+        let json = to_json(
+            &[entry("https://example.com/a")],
+            &[url("https://example.com/b")],
+        )
+        .unwrap();
+        let listed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            listed,
+            serde_json::json!([
+                {
+                    "url": "https://example.com/a",
+                    "file": "a.html",
+                    "status": "ok",
+                    "bytes": 4,
+                    "sha256": "abc",
+                },
+                {
+                    "url": "https://example.com/b",
+                    "file": null,
+                    "status": "error",
+                    "bytes": 0,
+                    "sha256": null,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn written_manifests_read_back() {
+        let mut edited = entry("https://example.com/edited");
+        edited.sha256 = Some("def".to_owned());
+        let json = to_json(
+            &[entry("https://example.com/same"), edited],
+            &[url("https://example.com/failed")],
+        )
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("rget-manifest-{}.json", std::process::id()));
+        std::fs::write(&path, json).unwrap();
+        let previous = Previous::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            previous.compare(&url("https://example.com/same"), "abc"),
+            Change::Unchanged
+        );
+        assert_eq!(
+            previous.compare(&url("https://example.com/edited"), "abc"),
+            Change::Changed
+        );
+        // failed urls weren't saved, so they're new when they are
+        assert_eq!(
+            previous.compare(&url("https://example.com/failed"), "abc"),
+            Change::Added
+        );
+    }
+
+    #[test]
+    fn hash_names_are_path_tab_url() {
+        let mut other = entry("https://example.com/b?q=1");
+        other.path = PathBuf::from("sub/0123abcd.html");
+        assert_eq!(
+            to_tsv(&[entry("https://example.com/a"), other]),
+            "a.html\thttps://example.com/a\nsub/0123abcd.html\thttps://example.com/b?q=1\n"
+        );
+    }
+
+    #[test]
+    fn json_line_status() {
+        let url = Url::parse("https://example.com/a").unwrap();
+        let line = |written, error| -> serde_json::Value {
+            serde_json::from_str(&json_line(&url, written, error).unwrap()).unwrap()
+        };
+
+        let ok = line(Some((PathBuf::from("a.html"), 12)), None);
+        assert_eq!(ok["status"], "ok");
+        assert_eq!(ok["path"], "a.html");
+        assert_eq!(ok["bytes"], 12);
+        let skipped = line(None, None);
+        assert_eq!(skipped["status"], "skipped");
+        assert!(skipped["path"].is_null());
+        let error = line(None, Some("code 404".to_owned()));
+        assert_eq!(error["status"], "error");
+        assert_eq!(error["error"], "code 404");
+    }
+}
@@ -0,0 +1,227 @@
+use reqwest::Url;
+use serde::Serialize;
+use tl::{HTMLTag, VDom};
+
+use crate::text::Readability;
+
+// What -m reports about a page
+#[derive(Serialize)]
+pub struct Metadata {
+    pub site: String,
+    pub num_links: usize,
+    pub images: usize,
+    pub last_fetch: String,
+    pub http_version: &'static str,
+    // the amp page this page was fetched in place of
+    pub amp_url: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    // charset the page was read as, and how sure we were when it was guessed
+    pub encoding: &'static str,
+    pub encoding_confidence: Option<f32>,
+    // the Content-Language header, which --accept-language may have chosen
+    pub content_language: Option<String>,
+    // with --extract-comments, in document order
+    pub comments: Option<Vec<String>>,
+    pub has_viewport_meta: bool,
+    pub viewport: Option<String>,
+    pub mobile_web_app_capable: Option<String>,
+    #[serde(flatten)]
+    pub readability: Option<Readability>,
+}
+
+impl Metadata {
+    pub fn render(&self) -> String {
+        let mut text = format!(
+            "site: {}\nnum_links: {}\nimages: {}\nlast_fetch: {}",
+            self.site, self.num_links, self.images, self.last_fetch
+        );
+        if let Some(language) = &self.content_language {
+            text.push_str(&format!("\ncontent_language: {}", language));
+        }
+        if let Some(viewport) = &self.viewport {
+            text.push_str(&format!("\nviewport: {}", viewport));
+        }
+        for comment in self.comments.iter().flatten() {
+            // one line each, whatever the comment spans
+            text.push_str(&format!("\ncomment: {}", comment.escape_debug()));
+        }
+        if let Some(stats) = &self.readability {
+            text.push_str(&format!(
+                "\nwords: {}\nreading_time: {}min\ntext_ratio: {:.2}",
+                stats.words, stats.reading_minutes, stats.text_ratio
+            ));
+        }
+        text
+    }
+}
+
+fn find_tag<'a>(dom: &'a VDom, pred: impl Fn(&HTMLTag) -> bool) -> Option<&'a HTMLTag<'a>> {
+    dom.nodes()
+        .iter()
+        .filter_map(|n| n.as_tag())
+        .find(|t| pred(t))
+}
+
+// Value of the attribute `name`, whatever case either is written in
+fn attr(tag: &HTMLTag, name: &str) -> Option<String> {
+    tag.attributes()
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, value)| value)
+        .map(|value| value.trim().to_owned())
+}
+
+fn has_attr(tag: &HTMLTag, name: &str) -> bool {
+    tag.attributes()
+        .iter()
+        .any(|(key, _)| key.eq_ignore_ascii_case(name))
+}
+
+// `<html amp>` or `<html ⚡>`, or the usual amp host and path conventions
+pub fn is_amp(url: &Url, dom: &VDom) -> bool {
+    let marked = find_tag(dom, |t| t.name().as_utf8_str().eq_ignore_ascii_case("html"))
+        .is_some_and(|html| {
+            // tl reads ⚡ as an attribute without a name, so look at the source
+            let raw = html.raw().as_utf8_str();
+            let open_tag = raw.split('>').next().unwrap_or_default();
+            has_attr(html, "amp") || open_tag.contains('⚡')
+        });
+    marked
+        || url.host_str().is_some_and(|h| h.starts_with("amp."))
+        || url
+            .path_segments()
+            .is_some_and(|mut s| s.any(|s| s == "amp"))
+}
+
+// href of <link rel="canonical">, as written in the source
+pub fn canonical(dom: &VDom) -> Option<String> {
+    let link = find_tag(dom, |t| {
+        t.name().as_utf8_str().eq_ignore_ascii_case("link")
+            && attr(t, "rel").is_some_and(|r| r.eq_ignore_ascii_case("canonical"))
+    })?;
+    attr(link, "href")
+}
+
+// `<!-- text -->` => `text`
+pub fn comment_text(raw: &str) -> String {
+    let text = raw.strip_prefix("<!--").unwrap_or(raw);
+    let text = text.strip_suffix("-->").unwrap_or(text);
+    text.trim().to_owned()
+}
+
+// Text of the <title>, as written in the source
+pub fn title(dom: &VDom) -> Option<String> {
+    let title = find_tag(dom, |t| {
+        t.name().as_utf8_str().eq_ignore_ascii_case("title")
+    })?;
+    Some(title.inner_text(dom.parser()).trim().to_owned())
+}
+
+// Content of <meta name="description">, as written in the source
+pub fn description(dom: &VDom) -> Option<String> {
+    meta_content(dom, "description")
+}
+
+// Content of <meta name="viewport">, as written in the source
+pub fn viewport(dom: &VDom) -> Option<String> {
+    meta_content(dom, "viewport")
+}
+
+pub fn mobile_web_app_capable(dom: &VDom) -> Option<String> {
+    meta_content(dom, "mobile-web-app-capable")
+}
+
+// Whether a viewport sizes the page to the device, `width=device-width, ...`
+pub fn fits_device_width(viewport: &str) -> bool {
+    viewport
+        .split([',', ';'])
+        .filter_map(|token| token.split_once('='))
+        .any(|(key, value)| {
+            key.trim().eq_ignore_ascii_case("width")
+                && value.trim().eq_ignore_ascii_case("device-width")
+        })
+}
+
+// Content of the <meta> named `name`, as written in the source
+fn meta_content(dom: &VDom, name: &str) -> Option<String> {
+    let meta = find_tag(dom, |t| {
+        t.name().as_utf8_str().eq_ignore_ascii_case("meta")
+            && attr(t, "name").is_some_and(|n| n.eq_ignore_ascii_case(name))
+    })?;
+    attr(meta, "content")
+}
+
+#[cfg(test)]
+mod tests {
+    use tl::{parse, ParserOptions};
+
+    use super::*;
+
+    fn dom(head: &str) -> VDom<'_> {
+        parse(head, ParserOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn viewport_and_app_capable_metas() {
+        let dom = dom(
+            "<head><META Name=\"Viewport\" content=\" width=device-width, initial-scale=1 \">\
+                       <meta name=\"mobile-web-app-capable\" content=\"yes\"></head>",
+        );
+        let viewport = viewport(&dom).unwrap();
+        assert_eq!(viewport, "width=device-width, initial-scale=1");
+        assert!(fits_device_width(&viewport));
+        assert_eq!(mobile_web_app_capable(&dom).as_deref(), Some("yes"));
+    }
+
+    #[test]
+    fn missing_tags() {
+        let dom = dom("<head><meta name=\"description\" content=\"d\"><title> T </title></head>");
+        assert_eq!(viewport(&dom), None);
+        assert_eq!(mobile_web_app_capable(&dom), None);
+        assert_eq!(description(&dom).as_deref(), Some("d"));
+        assert_eq!(title(&dom).as_deref(), Some("T"));
+        // a viewport without content is as good as none
+        assert_eq!(viewport(&self::dom("<meta name=\"viewport\">")), None);
+    }
+
+    #[test]
+    fn viewports_that_dont_fit_the_device() {
+        for content in [
+            "width=1024",
+            "initial-scale=1",
+            "width device-width",
+            "width=device-width-ish",
+            "",
+            ",,=;",
+        ] {
+            assert!(!fits_device_width(content), "{:?}", content);
+        }
+        assert!(fits_device_width("initial-scale=1; WIDTH = Device-Width"));
+    }
+
+    #[test]
+    fn amp_pages_and_canonical_links() {
+        let url = |u| Url::parse(u).unwrap();
+        let plain = dom("<html><head><link rel=\"Canonical\" href=\" /page \"></head></html>");
+        assert!(!is_amp(&url("https://example.com/page"), &plain));
+        assert!(is_amp(&url("https://amp.example.com/page"), &plain));
+        assert!(is_amp(&url("https://example.com/amp/page"), &plain));
+        assert!(is_amp(
+            &url("https://example.com/page"),
+            &dom("<html amp></html>")
+        ));
+        assert!(is_amp(
+            &url("https://example.com/page"),
+            &dom("<html ⚡></html>")
+        ));
+        assert_eq!(canonical(&plain).as_deref(), Some("/page"));
+        assert_eq!(canonical(&dom("<link rel=\"icon\" href=\"/i\">")), None);
+    }
+
+    #[test]
+    fn comment_markers_are_removed() {
+        assert_eq!(comment_text("<!-- note -->"), "note");
+        assert_eq!(comment_text("bare"), "bare");
+    }
+}
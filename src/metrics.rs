@@ -0,0 +1,173 @@
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing::{error, info};
+
+// quantiles are computed over this many of the latest fetches
+const DURATION_WINDOW: usize = 1024;
+const QUANTILES: &[f64] = &[0.5, 0.95, 0.99];
+
+// Live counters of a run, served to prometheus with --metrics-port
+#[derive(Default)]
+pub struct Metrics {
+    successes: AtomicUsize,
+    failures: AtomicUsize,
+    bytes: AtomicUsize,
+    durations: Mutex<VecDeque<f64>>,
+    duration_sum: Mutex<f64>,
+    // tasks started and not done yet, and how many of them are fetching
+    tasks: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+// Counts a task as in flight until dropped
+pub struct InFlightTask<'a>(&'a Metrics);
+
+impl Drop for InFlightTask<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn fetched(&self, ok: bool, duration: Duration) {
+        let counter = if ok { &self.successes } else { &self.failures };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let secs = duration.as_secs_f64();
+        *self.duration_sum.lock().unwrap() += secs;
+        let mut durations = self.durations.lock().unwrap();
+        if durations.len() == DURATION_WINDOW {
+            durations.pop_front();
+        }
+        durations.push_back(secs);
+    }
+
+    pub fn received(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn bytes_received(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn set_tasks(&self, tasks: usize) {
+        self.tasks.store(tasks, Ordering::Relaxed);
+    }
+
+    pub fn start_task(&self) -> InFlightTask<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightTask(self)
+    }
+
+    // The text exposition format prometheus scrapes
+    pub fn render(&self) -> String {
+        let successes = self.successes.load(Ordering::Relaxed);
+        let failures = self.failures.load(Ordering::Relaxed);
+        let in_flight = self.in_flight.load(Ordering::Relaxed);
+        let queued = self.tasks.load(Ordering::Relaxed).saturating_sub(in_flight);
+        let mut text = format!(
+            "# TYPE fetch_requests_total counter\n\
+             fetch_requests_total{{status=\"success\"}} {}\n\
+             fetch_requests_total{{status=\"failure\"}} {}\n\
+             # TYPE fetch_bytes_total counter\n\
+             fetch_bytes_total {}\n\
+             # TYPE fetch_duration_seconds summary\n",
+            successes,
+            failures,
+            self.bytes.load(Ordering::Relaxed)
+        );
+        let mut durations: Vec<f64> = self.durations.lock().unwrap().iter().copied().collect();
+        durations.sort_by(f64::total_cmp);
+        for q in QUANTILES {
+            let value = match durations.len() {
+                0 => f64::NAN,
+                len => durations[((len - 1) as f64 * q).round() as usize],
+            };
+            text.push_str(&format!(
+                "fetch_duration_seconds{{quantile=\"{}\"}} {}\n",
+                q, value
+            ));
+        }
+        text.push_str(&format!(
+            "fetch_duration_seconds_sum {}\n\
+             fetch_duration_seconds_count {}\n\
+             # TYPE tasks_in_flight gauge\n\
+             tasks_in_flight {}\n\
+             # TYPE tasks_queued gauge\n\
+             tasks_queued {}\n",
+            *self.duration_sum.lock().unwrap(),
+            successes + failures,
+            in_flight,
+            queued
+        ));
+        text
+    }
+}
+
+// The running metrics endpoint
+pub struct MetricsServer {
+    shutdown: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl MetricsServer {
+    // Serves `/metrics` on `addr` in the background
+    pub fn start(metrics: Arc<Metrics>, addr: SocketAddr) -> Result<Self> {
+        let make_service = make_service_fn(move |_| {
+            let metrics = metrics.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let metrics = metrics.clone();
+                    async move { Ok::<_, Infallible>(respond(&metrics, &req)) }
+                }))
+            }
+        });
+        let (shutdown, stopped) = oneshot::channel();
+        let server = Server::try_bind(&addr)?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            });
+        info!("serving metrics on http://{}/metrics", addr);
+        let handle = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("metrics server failed : {}", e);
+            }
+        });
+        Ok(Self { shutdown, handle })
+    }
+
+    // Lets in-progress scrapes finish, then stops
+    pub async fn stop(self) {
+        self.shutdown.send(()).ok();
+        self.handle.await.ok();
+    }
+}
+
+fn respond(metrics: &Metrics, req: &Request<Body>) -> Response<Body> {
+    if req.uri().path() != "/metrics" {
+        let mut resp = Response::new(Body::from("not found\n"));
+        *resp.status_mut() = StatusCode::NOT_FOUND;
+        return resp;
+    }
+    let mut resp = Response::new(Body::from(metrics.render()));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    resp
+}
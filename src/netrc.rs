@@ -0,0 +1,170 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+
+// Credentials of a machine, or of the `default` entry
+#[derive(Default)]
+pub struct Login {
+    pub login: String,
+    pub password: Option<String>,
+}
+
+// Per-host credentials read from a .netrc file, as used by curl and wget
+#[derive(Default)]
+pub struct Netrc {
+    machines: HashMap<String, Login>,
+    default: Option<Login>,
+}
+
+impl Netrc {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let netrc =
+            std::fs::read_to_string(path).with_context(|| format!("can't read {:?}", path))?;
+        Self::parse(&netrc).with_context(|| format!("invalid netrc file {:?}", path))
+    }
+
+    // ~/.netrc, if there is one
+    pub fn from_home() -> Result<Option<Self>> {
+        let path = match std::env::var_os("HOME") {
+            Some(home) => Path::new(&home).join(".netrc"),
+            None => return Ok(None),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::from_file(&path).map(Some)
+    }
+
+    // Whitespace separated `machine NAME login NAME password SECRET` entries,
+    // `default` applies to any other machine and `macdef` macros are skipped
+    fn parse(netrc: &str) -> Result<Self> {
+        let mut parsed = Self::default();
+        // the entry being read, None for default
+        let mut current: Option<(Option<String>, Login)> = None;
+        let mut lines = netrc.lines();
+        while let Some(line) = lines.next() {
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                let mut value = || {
+                    tokens
+                        .next()
+                        .ok_or_else(|| anyhow!("missing value after {}", token))
+                };
+                match token {
+                    "machine" | "default" => {
+                        let machine = match token {
+                            "machine" => Some(value()?.to_ascii_lowercase()),
+                            _ => None,
+                        };
+                        if let Some((machine, login)) = current.replace((machine, Login::default()))
+                        {
+                            parsed.add(machine, login);
+                        }
+                    }
+                    "login" | "password" | "account" => {
+                        let value = value()?.to_owned();
+                        let (_, login) = current
+                            .as_mut()
+                            .ok_or_else(|| anyhow!("{} outside of a machine entry", token))?;
+                        match token {
+                            "login" => login.login = value,
+                            "password" => login.password = Some(value),
+                            _ => {}
+                        }
+                    }
+                    "macdef" => {
+                        // the macro runs until an empty line
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                    // comments run to the end of the line
+                    token if token.starts_with('#') => break,
+                    token => return Err(anyhow!("unexpected {:?}", token)),
+                }
+            }
+        }
+        if let Some((machine, login)) = current {
+            parsed.add(machine, login);
+        }
+        Ok(parsed)
+    }
+
+    fn add(&mut self, machine: Option<String>, login: Login) {
+        match machine {
+            // the first entry for a machine wins, like curl
+            Some(machine) => {
+                self.machines.entry(machine).or_insert(login);
+            }
+            None => self.default = Some(login),
+        }
+    }
+
+    pub fn credentials(&self, host: &str) -> Option<&Login> {
+        self.machines
+            .get(&host.to_ascii_lowercase())
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETRC: &str = "\
+# credentials for the mirrors
+machine Mirror.example.com login alice password s3cret
+machine other.example.com
+    login bob
+    password hunter2
+
+macdef init
+cd /pub
+machine evil.example.com login mallory password nope
+
+machine mirror.example.com login shadowed password ignored
+default login anonymous password guest@
+";
+
+    #[test]
+    fn machines_and_default() {
+        let netrc = Netrc::parse(NETRC).unwrap();
+        let alice = netrc.credentials("mirror.example.com").unwrap();
+        assert_eq!(alice.login, "alice");
+        assert_eq!(alice.password.as_deref(), Some("s3cret"));
+        // host names are case insensitive
+        let bob = netrc.credentials("OTHER.example.com").unwrap();
+        assert_eq!(bob.login, "bob");
+        assert_eq!(bob.password.as_deref(), Some("hunter2"));
+        let anonymous = netrc.credentials("unknown.example.com").unwrap();
+        assert_eq!(anonymous.login, "anonymous");
+        assert_eq!(anonymous.password.as_deref(), Some("guest@"));
+    }
+
+    #[test]
+    fn macros_are_skipped() {
+        let netrc = Netrc::parse(NETRC).unwrap();
+        // only in the body of the macro, so it falls back to default
+        assert_eq!(
+            netrc.credentials("evil.example.com").unwrap().login,
+            "anonymous"
+        );
+    }
+
+    #[test]
+    fn no_default_without_a_default_entry() {
+        let netrc = Netrc::parse("machine a.example.com login a").unwrap();
+        assert!(netrc.credentials("b.example.com").is_none());
+        assert_eq!(netrc.credentials("a.example.com").unwrap().password, None);
+    }
+
+    #[test]
+    fn malformed_files() {
+        assert!(Netrc::parse("machine").is_err());
+        assert!(Netrc::parse("login alice").is_err());
+        assert!(Netrc::parse("machine a.example.com user alice").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+use tokio::fs;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathConflict {
+    Skip,
+    Rename,
+    Error,
+}
+
+impl FromStr for PathConflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "rename" => Ok(Self::Rename),
+            "error" => Ok(Self::Error),
+            _ => Err(anyhow!("unknown path conflict policy: {}", s)),
+        }
+    }
+}
+
+// How file names are derived from urls
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameScheme {
+    // host and path, `example.com_dir_page.html`
+    Flat,
+    // sha256 of the url plus its extension, unique and fixed length
+    Hash,
+}
+
+impl FromStr for NameScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "flat" => Ok(Self::Flat),
+            "hash" => Ok(Self::Hash),
+            _ => Err(anyhow!("unknown name scheme: {}", s)),
+        }
+    }
+}
+
+// Index of the first component of `path` that clashes with the filesystem:
+// either an ancestor that exists as a file, or the path itself existing as a
+// directory.
+async fn find_conflict(path: &Path) -> Option<usize> {
+    let components: Vec<_> = path.components().collect();
+    let mut prefix = PathBuf::new();
+    for (i, c) in components.iter().enumerate() {
+        prefix.push(c);
+        if !matches!(c, Component::Normal(_)) {
+            continue;
+        }
+        let is_last = i + 1 == components.len();
+        match fs::metadata(&prefix).await {
+            Ok(m) if is_last && m.is_dir() => return Some(i),
+            Ok(m) if !is_last && !m.is_dir() => return Some(i),
+            Ok(_) => {}
+            // nothing further down can exist
+            Err(_) => return None,
+        }
+    }
+    None
+}
+
+fn with_suffix(path: &Path, index: usize, n: usize) -> PathBuf {
+    path.components()
+        .enumerate()
+        .map(|(i, c)| {
+            if i == index {
+                let mut name = OsString::from(c.as_os_str());
+                name.push(format!(".{}", n));
+                name
+            } else {
+                c.as_os_str().to_owned()
+            }
+        })
+        .collect()
+}
+
+// Returns the path the output should be written to, or None if it should be
+// skipped, according to `policy`.
+pub async fn resolve_conflict(path: &Path, policy: PathConflict) -> Result<Option<PathBuf>> {
+    let mut candidate = path.to_owned();
+    let mut n = 0;
+    while let Some(index) = find_conflict(&candidate).await {
+        match policy {
+            PathConflict::Skip => {
+                warn!("skipping {:?}: conflicts with an existing path", path);
+                return Ok(None);
+            }
+            PathConflict::Error => {
+                return Err(anyhow!("{:?} conflicts with an existing path", path));
+            }
+            PathConflict::Rename => {
+                n += 1;
+                candidate = with_suffix(path, index, n);
+            }
+        }
+    }
+    if candidate != path {
+        warn!(
+            "{:?} conflicts with an existing path, saving as {:?}",
+            path, candidate
+        );
+    }
+    Ok(Some(candidate))
+}
+
+// Path of `to` relative to the directory containing `from`, as used in links
+pub fn relative_path(from: &Path, to: &Path) -> String {
+    let from: Vec<_> = from.parent().map_or(vec![], |p| p.components().collect());
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts = vec![".."; from.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_str().unwrap_or_default()),
+    );
+    parts.join("/")
+}
+
+// Written next to the target then renamed over it, so a crash mid-write
+// leaves the previous version intact
+pub async fn write_atomic(path: &Path, data: impl AsRef<[u8]>) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, data).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty directory of the test's own
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rget-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn file_then_dir() {
+        // `/foo` was saved as a file, now `/foo/bar` wants it to be a directory
+        let dir = tempdir("file-then-dir");
+        std::fs::write(dir.join("foo"), "page").unwrap();
+        let path = dir.join("foo").join("bar");
+
+        let renamed = resolve_conflict(&path, PathConflict::Rename).await.unwrap();
+        assert_eq!(renamed, Some(dir.join("foo.1").join("bar")));
+        assert_eq!(
+            resolve_conflict(&path, PathConflict::Skip).await.unwrap(),
+            None
+        );
+        assert!(resolve_conflict(&path, PathConflict::Error).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dir_then_file() {
+        // `/foo/bar` made `/foo` a directory, now `/foo` itself is saved
+        let dir = tempdir("dir-then-file");
+        std::fs::create_dir_all(dir.join("foo")).unwrap();
+        std::fs::write(dir.join("foo").join("bar"), "page").unwrap();
+        let path = dir.join("foo");
+
+        let renamed = resolve_conflict(&path, PathConflict::Rename).await.unwrap();
+        assert_eq!(renamed, Some(dir.join("foo.1")));
+        assert_eq!(
+            resolve_conflict(&path, PathConflict::Skip).await.unwrap(),
+            None
+        );
+        assert!(resolve_conflict(&path, PathConflict::Error).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn renames_past_taken_suffixes() {
+        let dir = tempdir("taken-suffixes");
+        std::fs::create_dir_all(dir.join("foo")).unwrap();
+        std::fs::create_dir_all(dir.join("foo.1")).unwrap();
+        let renamed = resolve_conflict(&dir.join("foo"), PathConflict::Rename)
+            .await
+            .unwrap();
+        assert_eq!(renamed, Some(dir.join("foo.2")));
+    }
+
+    #[tokio::test]
+    async fn no_conflict() {
+        let dir = tempdir("no-conflict");
+        std::fs::create_dir_all(dir.join("foo")).unwrap();
+        for path in [dir.join("foo").join("bar"), dir.join("new").join("page")] {
+            let resolved = resolve_conflict(&path, PathConflict::Error).await.unwrap();
+            assert_eq!(resolved, Some(path));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use reqwest::{
+    header::{HeaderMap, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LAST_MODIFIED, RANGE},
+    Client, StatusCode, Url,
+};
+use serde::Serialize;
+use tracing::debug;
+
+// What a HEAD request told us about a url
+#[derive(Serialize)]
+pub struct Probe {
+    pub url: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    pub last_modified: Option<String>,
+}
+
+fn header(headers: &HeaderMap, name: impl reqwest::header::AsHeaderName) -> Option<String> {
+    headers.get(name)?.to_str().ok().map(str::to_owned)
+}
+
+pub async fn probe(client: &Client, url: &Url) -> Result<Probe> {
+    let mut resp = client.head(url.clone()).send().await?;
+    let mut content_length = header(resp.headers(), CONTENT_LENGTH).and_then(|l| l.parse().ok());
+    if matches!(
+        resp.status(),
+        StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+    ) {
+        debug!("HEAD not supported by {}, asking for the first byte", url);
+        resp = client
+            .get(url.clone())
+            .header(RANGE, "bytes=0-0")
+            .send()
+            .await?;
+        // `bytes 0-0/1234`, the full length is after the slash
+        content_length = match header(resp.headers(), CONTENT_RANGE) {
+            Some(range) => range.rsplit('/').next().and_then(|l| l.parse().ok()),
+            None => header(resp.headers(), CONTENT_LENGTH).and_then(|l| l.parse().ok()),
+        };
+    }
+    let headers = resp.headers();
+    Ok(Probe {
+        url: url.to_string(),
+        status: resp.status().as_u16(),
+        content_type: header(headers, CONTENT_TYPE),
+        content_length,
+        last_modified: header(headers, LAST_MODIFIED),
+    })
+}
+
+pub fn render_table(probes: &[Probe]) -> String {
+    let rows: Vec<[String; 5]> = probes
+        .iter()
+        .map(|p| {
+            [
+                p.status.to_string(),
+                p.content_type.clone().unwrap_or_else(|| "-".into()),
+                p.content_length.map_or("-".into(), |l| l.to_string()),
+                p.last_modified.clone().unwrap_or_else(|| "-".into()),
+                p.url.clone(),
+            ]
+        })
+        .collect();
+    let header = ["STATUS", "CONTENT-TYPE", "LENGTH", "LAST-MODIFIED", "URL"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(cell.len());
+        }
+    }
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| format!("{:w$}", cell, w = w))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use anyhow::Result;
+use reqwest::{header::CONTENT_TYPE, Response};
+
+// Saved pages don't keep their headers, so go by extension and then by the first bytes
+fn guess_content_type(path: &Path, body: &[u8]) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    match ext.to_ascii_lowercase().as_str() {
+        "html" | "htm" => return "text/html",
+        "css" => return "text/css",
+        "js" => return "application/javascript",
+        "txt" => return "text/plain",
+        "png" => return "image/png",
+        "jpg" | "jpeg" => return "image/jpeg",
+        "gif" => return "image/gif",
+        "svg" => return "image/svg+xml",
+        _ => {}
+    }
+    let head = String::from_utf8_lossy(&body[..body.len().min(256)]).to_ascii_lowercase();
+    let head = head.trim_start();
+    if head.starts_with("<!doctype html") || head.starts_with("<html") {
+        "text/html"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+// Builds a response out of a previously saved copy of a url, if there is one
+pub async fn replay(path: &Path) -> Result<Option<Response>> {
+    let body = match tokio::fs::read(path).await {
+        Ok(body) => body,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let resp = ::http::Response::builder()
+        .header(CONTENT_TYPE, guess_content_type(path, &body))
+        .body(body)?;
+    Ok(Some(resp.into()))
+}
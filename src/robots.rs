@@ -0,0 +1,287 @@
+use std::{collections::HashMap, sync::Arc, sync::Mutex, time::Duration};
+
+use reqwest::{Client, Url};
+use tokio::sync::OnceCell;
+use tracing::{debug, info};
+
+// the name we look for in User-agent lines, besides `*`
+const AGENT: &str = "rget";
+
+// Allow/Disallow rules of the group that applies to us
+#[derive(Default)]
+struct Rules {
+    // (path prefix, allowed)
+    rules: Vec<(String, bool)>,
+    // minimum time between requests, from Crawl-delay
+    delay: Option<Duration>,
+}
+
+impl Rules {
+    fn parse(robots: &str) -> Self {
+        let mut ours = Vec::new();
+        let mut any = Vec::new();
+        let mut our_delay = None;
+        let mut any_delay = None;
+        let mut found_ours = false;
+        // user agents of the group being read, and whether its rules started
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+        for line in robots.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_ascii_lowercase(), value.trim()),
+                None => continue,
+            };
+            match key.as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_ascii_lowercase());
+                }
+                "allow" | "disallow" => {
+                    in_rules = true;
+                    // an empty Disallow allows everything
+                    if value.is_empty() {
+                        continue;
+                    }
+                    let rule = (value.to_owned(), key == "allow");
+                    if agents.iter().any(|a| a == AGENT) {
+                        found_ours = true;
+                        ours.push(rule);
+                    } else if agents.iter().any(|a| a == "*") {
+                        any.push(rule);
+                    }
+                }
+                "crawl-delay" => {
+                    in_rules = true;
+                    let delay = match value.parse::<f64>() {
+                        Ok(secs) if secs.is_finite() && secs >= 0.0 => {
+                            Duration::from_secs_f64(secs)
+                        }
+                        _ => continue,
+                    };
+                    if agents.iter().any(|a| a == AGENT) {
+                        found_ours = true;
+                        our_delay = Some(delay);
+                    } else if agents.iter().any(|a| a == "*") {
+                        any_delay = Some(delay);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if found_ours {
+            Self {
+                rules: ours,
+                delay: our_delay,
+            }
+        } else {
+            Self {
+                rules: any,
+                delay: any_delay,
+            }
+        }
+    }
+
+    // The longest matching rule wins, Allow wins ties
+    fn allows(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| matches(prefix, path))
+            .max_by_key(|(prefix, allow)| (prefix.len(), *allow))
+            .is_none_or(|(_, allow)| *allow)
+    }
+}
+
+// Prefix match supporting the `*` wildcard and `$` end anchor
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let mut rest = match path.strip_prefix(parts[0]) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        if anchored && i == parts.len() - 1 {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    !anchored || rest.is_empty()
+}
+
+// robots.txt of every origin seen in a run, fetched once
+#[derive(Default)]
+pub struct Robots {
+    origins: Mutex<HashMap<String, Arc<OnceCell<Rules>>>>,
+}
+
+impl Robots {
+    async fn rules(&self, client: &Client, url: &Url) -> Arc<OnceCell<Rules>> {
+        let origin = url.origin().ascii_serialization();
+        let cell = self
+            .origins
+            .lock()
+            .unwrap()
+            .entry(origin.clone())
+            .or_default()
+            .clone();
+        cell.get_or_init(|| fetch(client, origin)).await;
+        cell
+    }
+
+    pub async fn allows(&self, client: &Client, url: &Url) -> bool {
+        let cell = self.rules(client, url).await;
+        let rules = cell.get().expect("robots.txt was fetched");
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        };
+        rules.allows(&path)
+    }
+
+    // The Crawl-delay of `url`'s origin, if its robots.txt has one for us
+    pub async fn crawl_delay(&self, client: &Client, url: &Url) -> Option<Duration> {
+        let cell = self.rules(client, url).await;
+        cell.get().and_then(|rules| rules.delay)
+    }
+}
+
+// A missing or unreadable robots.txt allows everything
+async fn fetch(client: &Client, origin: String) -> Rules {
+    let url = format!("{}/robots.txt", origin);
+    let resp = match client.get(&url).send().await {
+        Ok(resp) if resp.status().is_success() => resp,
+        Ok(resp) => {
+            debug!("no robots.txt at {} : code {:?}", url, resp.status());
+            return Rules::default();
+        }
+        Err(e) => {
+            debug!("couldn't fetch {} : {}", url, e);
+            return Rules::default();
+        }
+    };
+    match resp.text().await {
+        Ok(text) => {
+            info!("using {}", url);
+            let rules = Rules::parse(&text);
+            if let Some(delay) = rules.delay {
+                info!(
+                    "waiting {:?} between requests to {}, per its Crawl-delay",
+                    delay, origin
+                );
+            }
+            rules
+        }
+        Err(_) => Rules::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_and_anchors() {
+        let cases = [
+            ("/private", "/private/x", true),
+            ("/private", "/pub", false),
+            ("/", "/", true),
+            ("*", "/anything", true),
+            ("/*.php", "/a/b.php?x=1", true),
+            ("/*.php", "/a/b.html", false),
+            ("/*.php$", "/a/b.php", true),
+            ("/*.php$", "/a/b.php?x=1", false),
+            ("/fish$", "/fish", true),
+            ("/fish$", "/fish/", false),
+            ("/a*b*c", "/axxbyyc", true),
+            ("/a*b*c", "/axxc", false),
+        ];
+        for (pattern, path, expected) in cases {
+            assert_eq!(matches(pattern, path), expected, "{} {}", pattern, path);
+        }
+    }
+
+    #[test]
+    fn longest_match_wins() {
+        let rules = Rules::parse(
+            "User-agent: *\n\
+             Disallow: /private\n\
+             Allow: /private/public\n\
+             Disallow: /*.pdf$ # no documents\n\
+             Crawl-delay: 2\n\
+             \n\
+             User-agent: googlebot\n\
+             Disallow: /\n",
+        );
+        let cases = [
+            ("/", true),
+            ("/private/x", false),
+            ("/private/public/y", true),
+            ("/doc.pdf", false),
+            ("/doc.pdf?x=1", true),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(rules.allows(path), expected, "{}", path);
+        }
+        assert_eq!(rules.delay, Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn allow_wins_ties() {
+        let rules = Rules::parse("User-agent: *\nDisallow: /page\nAllow: /page\n");
+        assert!(rules.allows("/page"));
+    }
+
+    #[test]
+    fn our_group_replaces_the_default_one() {
+        let rules = Rules::parse(
+            "User-agent: *\n\
+             Disallow: /\n\
+             Crawl-delay: 5\n\
+             \n\
+             User-agent: other\n\
+             User-agent: RGET\n\
+             Disallow: /secret\n\
+             Crawl-delay: 0.5\n",
+        );
+        let cases = [("/", true), ("/page", true), ("/secret/x", false)];
+        for (path, expected) in cases {
+            assert_eq!(rules.allows(path), expected, "{}", path);
+        }
+        assert_eq!(rules.delay, Some(Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn lenient_parsing() {
+        let cases = [
+            // an empty Disallow allows everything
+            ("User-agent: *\nDisallow:\n", "/x", true, None),
+            ("user-agent: *\ndisallow: /x\n", "/x", false, None),
+            // rules of other agents don't apply
+            ("User-agent: bot\nDisallow: /\n", "/x", true, None),
+            ("User-agent: *\nCrawl-delay: soon\n", "/x", true, None),
+            ("User-agent: *\nCrawl-delay: -1\n", "/x", true, None),
+            (
+                "User-agent: *\nCrawl-delay: 1.5\n",
+                "/x",
+                true,
+                Some(Duration::from_millis(1500)),
+            ),
+            ("", "/x", true, None),
+        ];
+        for (robots, path, allowed, delay) in cases {
+            let rules = Rules::parse(robots);
+            assert_eq!(rules.allows(path), allowed, "{:?}", robots);
+            assert_eq!(rules.delay, delay, "{:?}", robots);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+
+use reqwest::Url;
+
+// how many robots.txt-blocked urls the summary lists
+const BLOCKED_EXAMPLES: usize = 3;
+
+// Counters accumulated over a run and reported at the end
+#[derive(Default)]
+pub struct Stats {
+    pub slow: AtomicUsize,
+    pub robots_skipped: AtomicUsize,
+    pub mixed_content: AtomicUsize,
+    pub broken_links: AtomicUsize,
+    // urls dropped because --max-queue-depth was reached
+    pub queue_capped: AtomicUsize,
+    robots_examples: Mutex<Vec<Url>>,
+    pub over_time_budget: AtomicUsize,
+    abandoned_hosts: Mutex<Vec<String>>,
+    // pages left unsaved by --html-only
+    pub skipped_non_html: AtomicUsize,
+    // files compared with --since-manifest
+    pub added: AtomicUsize,
+    pub changed: AtomicUsize,
+    pub unchanged: AtomicUsize,
+}
+
+impl Stats {
+    pub fn incr(counter: &AtomicUsize) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn robots_blocked(&self, url: &Url) {
+        Self::incr(&self.robots_skipped);
+        let mut examples = self.robots_examples.lock().unwrap();
+        if examples.len() < BLOCKED_EXAMPLES {
+            examples.push(url.clone());
+        }
+    }
+
+    pub fn host_abandoned(&self, host: &str) {
+        Self::incr(&self.over_time_budget);
+        let mut hosts = self.abandoned_hosts.lock().unwrap();
+        if !hosts.iter().any(|h| h == host) {
+            hosts.push(host.to_owned());
+        }
+    }
+
+    // the counts of the --since-manifest changelog
+    pub fn changes(&self, removed: usize) -> String {
+        format!(
+            "added: {}\nchanged: {}\nunchanged: {}\nremoved: {}",
+            self.added.load(Ordering::Relaxed),
+            self.changed.load(Ordering::Relaxed),
+            self.unchanged.load(Ordering::Relaxed),
+            removed
+        )
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "slow_urls: {}\nmixed_content: {}\nrobots_skipped: {}",
+            self.slow.load(Ordering::Relaxed),
+            self.mixed_content.load(Ordering::Relaxed),
+            self.robots_skipped.load(Ordering::Relaxed)
+        );
+        for url in self.robots_examples.lock().unwrap().iter() {
+            summary.push_str(&format!("\n  {}", url));
+        }
+        summary.push_str(&format!(
+            "\nbroken_links: {}",
+            self.broken_links.load(Ordering::Relaxed)
+        ));
+        let capped = self.queue_capped.load(Ordering::Relaxed);
+        summary.push_str(&format!("\nqueue_capped: {}", capped));
+        if capped > 0 {
+            summary.push_str("\n  discovery halted due to queue cap");
+        }
+        summary.push_str(&format!(
+            "\nover_time_budget: {}",
+            self.over_time_budget.load(Ordering::Relaxed)
+        ));
+        for host in self.abandoned_hosts.lock().unwrap().iter() {
+            summary.push_str(&format!("\n  {}", host));
+        }
+        summary.push_str(&format!(
+            "\nskipped_non_html: {}",
+            self.skipped_non_html.load(Ordering::Relaxed)
+        ));
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn robots_blocked_urls_are_counted() {
+        let stats = Stats::default();
+        for i in 0..5 {
+            let url = Url::parse(&format!("https://example.com/private/{}", i)).unwrap();
+            stats.robots_blocked(&url);
+        }
+        assert_eq!(stats.robots_skipped.load(Ordering::Relaxed), 5);
+        let summary = stats.summary();
+        assert!(summary.contains("robots_skipped: 5\n"), "{}", summary);
+        // every url counts, only the first few are listed
+        assert!(
+            summary.contains("\n  https://example.com/private/2\n"),
+            "{}",
+            summary
+        );
+        assert!(!summary.contains("/private/3"), "{}", summary);
+    }
+}
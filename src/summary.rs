@@ -0,0 +1,148 @@
+use std::{path::PathBuf, time::Duration};
+
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::error::FetchError;
+
+// A url the run saved
+pub struct FetchedItem {
+    pub url: Url,
+    pub path: PathBuf,
+    pub status: u16,
+    pub bytes: u64,
+    // from the start of its task to the file being written
+    pub duration: Duration,
+}
+
+// What a run did, for --fetch-summary
+pub struct FetchSummary {
+    pub fetched: Vec<FetchedItem>,
+    pub errors: Vec<FetchError>,
+    pub robots_blocked: usize,
+    // received from the network, before any rewriting
+    pub total_bytes: u64,
+    pub elapsed: Duration,
+    // stopped by Ctrl-C with --graceful-shutdown before the crawl was done
+    pub interrupted: bool,
+}
+
+#[derive(Serialize)]
+struct ItemJson<'a> {
+    url: &'a str,
+    path: String,
+    status: u16,
+    bytes: u64,
+    duration_secs: f64,
+}
+
+#[derive(Serialize)]
+struct SummaryJson<'a> {
+    fetched: Vec<ItemJson<'a>>,
+    errors: Vec<String>,
+    robots_blocked: usize,
+    total_bytes: u64,
+    elapsed_secs: f64,
+}
+
+impl FetchSummary {
+    pub fn render(&self) -> String {
+        let mut text = format!("fetched: {}", self.fetched.len());
+        for item in &self.fetched {
+            text.push_str(&format!(
+                "\n  {} {} => {} ({} bytes, {:.3}s)",
+                item.status,
+                item.url,
+                item.path.display(),
+                item.bytes,
+                item.duration.as_secs_f64()
+            ));
+        }
+        text.push_str(&format!("\nerrors: {}", self.errors.len()));
+        for error in &self.errors {
+            text.push_str(&format!("\n  {}", error));
+        }
+        text.push_str(&format!(
+            "\nrobots_blocked: {}\ntotal_bytes: {}\nelapsed: {:.3}s",
+            self.robots_blocked,
+            self.total_bytes,
+            self.elapsed.as_secs_f64()
+        ));
+        text
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        let fetched = self
+            .fetched
+            .iter()
+            .map(|item| ItemJson {
+                url: item.url.as_str(),
+                path: item.path.to_string_lossy().into_owned(),
+                status: item.status,
+                bytes: item.bytes,
+                duration_secs: item.duration.as_secs_f64(),
+            })
+            .collect();
+        serde_json::to_string_pretty(&SummaryJson {
+            fetched,
+            errors: self.errors.iter().map(ToString::to_string).collect(),
+            robots_blocked: self.robots_blocked,
+            total_bytes: self.total_bytes,
+            elapsed_secs: self.elapsed.as_secs_f64(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::*;
+
+    fn summary() -> FetchSummary {
+        let url = |path| Url::parse(&format!("https://example.com/{}", path)).unwrap();
+        FetchSummary {
+            fetched: vec![FetchedItem {
+                url: url("a.html"),
+                path: PathBuf::from("example.com_a.html"),
+                status: 200,
+                bytes: 1234,
+                duration: Duration::from_millis(250),
+            }],
+            errors: vec![FetchError::Http {
+                url: url("missing"),
+                status: StatusCode::NOT_FOUND,
+            }],
+            robots_blocked: 2,
+            total_bytes: 4321,
+            elapsed: Duration::from_millis(1500),
+            interrupted: false,
+        }
+    }
+
+    #[test]
+    fn renders_every_field() {
+        assert_eq!(
+            summary().render(),
+            "fetched: 1\n  \
+             200 https://example.com/a.html => example.com_a.html (1234 bytes, 0.250s)\n\
+             errors: 1\n  \
+             Error while fetching https://example.com/missing : code 404\n\
+             robots_blocked: 2\ntotal_bytes: 4321\nelapsed: 1.500s"
+        );
+    }
+
+    #[test]
+    fn json_has_every_field() {
+        let json: serde_json::Value = serde_json::from_str(&summary().to_json().unwrap()).unwrap();
+        assert_eq!(json["fetched"][0]["url"], "https://example.com/a.html");
+        assert_eq!(json["fetched"][0]["path"], "example.com_a.html");
+        assert_eq!(json["fetched"][0]["status"], 200);
+        assert_eq!(json["fetched"][0]["bytes"], 1234);
+        assert_eq!(json["fetched"][0]["duration_secs"], 0.25);
+        assert_eq!(json["errors"].as_array().unwrap().len(), 1);
+        assert_eq!(json["robots_blocked"], 2);
+        assert_eq!(json["total_bytes"], 4321);
+        assert_eq!(json["elapsed_secs"], 1.5);
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use reqwest::Url;
+
+// placeholders filled in from the url being saved
+const URL_VARS: &[&str] = &["host", "path", "name"];
+
+// Output filename template, e.g. `{date}/{host}/{path}`
+pub struct OutputTemplate {
+    template: String,
+    vars: HashMap<String, String>,
+}
+
+fn placeholders(template: &str) -> Result<Vec<&str>> {
+    let mut names = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unterminated placeholder in template {:?}", template))?;
+        names.push(&rest[start + 1..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    Ok(names)
+}
+
+impl OutputTemplate {
+    // Builds a template with the built-in run variables plus `custom` ones,
+    // failing if the template references anything undefined.
+    pub fn new(template: &str, custom: Vec<(String, String)>) -> Result<Self> {
+        let now = chrono::Local::now();
+        let mut vars = HashMap::new();
+        vars.insert("date".to_owned(), now.format("%Y-%m-%d").to_string());
+        vars.insert("time".to_owned(), now.format("%H%M%S").to_string());
+        vars.insert(
+            "runid".to_owned(),
+            format!("{}-{}", now.format("%Y%m%d%H%M%S"), std::process::id()),
+        );
+        vars.extend(custom);
+        for name in placeholders(template)? {
+            if !URL_VARS.contains(&name) && !vars.contains_key(name) {
+                return Err(anyhow!("undefined template variable {{{}}}", name));
+            }
+        }
+        Ok(Self {
+            template: template.to_owned(),
+            vars,
+        })
+    }
+
+    pub fn render(&self, url: &Url, name: &str) -> String {
+        let mut path = url.path().trim_start_matches('/').to_owned();
+        if path.is_empty() || path.ends_with('/') {
+            path.push_str("index.html");
+        }
+        let mut out = self.template.clone();
+        for (key, value) in &self.vars {
+            out = out.replace(&format!("{{{}}}", key), value);
+        }
+        // url parts go last so their content is never expanded
+        out.replace("{host}", url.host_str().unwrap_or_default())
+            .replace("{path}", &path)
+            .replace("{name}", name)
+    }
+}
+
+// Parses a `key=value` template variable definition
+pub fn parse_var(var: &str) -> Result<(String, String)> {
+    let (key, value) = var
+        .split_once('=')
+        .ok_or_else(|| anyhow!("template variable {:?} is not in key=value form", var))?;
+    if URL_VARS.contains(&key) {
+        return Err(anyhow!("template variable {{{}}} is reserved", key));
+    }
+    Ok((key.to_owned(), value.to_owned()))
+}
@@ -0,0 +1,170 @@
+use serde::Serialize;
+use tl::{Node, Parser, VDom};
+
+// contents of these are never rendered as text
+const SKIPPED: &[&str] = &["head", "script", "style", "template", "noscript"];
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+// a typical adult's silent reading speed
+const WORDS_PER_MINUTE: usize = 200;
+
+// Rough readability figures for a page
+#[derive(Serialize)]
+pub struct Readability {
+    pub words: usize,
+    pub reading_minutes: usize,
+    // share of the html that is visible text
+    pub text_ratio: f64,
+}
+
+impl Readability {
+    pub fn of(text: &str, html_len: usize) -> Self {
+        let words = text.split_whitespace().count();
+        Self {
+            words,
+            reading_minutes: words.div_ceil(WORDS_PER_MINUTE),
+            text_ratio: match html_len {
+                0 => 0.0,
+                len => text.len() as f64 / len as f64,
+            },
+        }
+    }
+}
+
+// Visible text of a document: one line per block element, whitespace collapsed
+pub fn extract_text(dom: &VDom) -> String {
+    let parser = dom.parser();
+    let mut raw = String::new();
+    for handle in dom.children() {
+        if let Some(node) = handle.get(parser) {
+            walk(node, parser, &mut raw);
+        }
+    }
+    let mut text = String::with_capacity(raw.len());
+    for line in raw.lines() {
+        let line = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !line.is_empty() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+fn walk(node: &Node, parser: &Parser, out: &mut String) {
+    match node {
+        Node::Raw(text) => {
+            // line breaks in the source are just whitespace, only blocks break lines
+            let text =
+                html_escape::decode_html_entities(&text.as_utf8_str()).replace(['\r', '\n'], " ");
+            out.push_str(&text);
+        }
+        Node::Tag(tag) => {
+            let name = tag.name().as_utf8_str().to_ascii_lowercase();
+            if SKIPPED.contains(&name.as_str()) {
+                return;
+            }
+            let block = BLOCKS.contains(&name.as_str());
+            if block {
+                out.push('\n');
+            }
+            for handle in tag.children().top().iter() {
+                if let Some(child) = handle.get(parser) {
+                    walk(child, parser, out);
+                }
+            }
+            if block {
+                out.push('\n');
+            }
+        }
+        Node::Comment(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tl::{parse, ParserOptions};
+
+    use super::*;
+
+    const PAGE: &str = "<html><head><title>Not shown</title>\
+        <style>body { color: red }</style></head>\
+        <body><script>var hidden = 1;</script>\
+        <h1>Title</h1>\
+        <p>First   paragraph,\n spread over lines &amp; with an <a href=\"/\">inline link</a>.</p>\
+        <div>A div<br>after a break</div>\
+        <ul><li>one</li><li>two</li></ul>\
+        <noscript>enable js</noscript>\
+        <!-- a comment -->\
+        <table><tr><td>cell</td><td>next</td></tr></table>\
+        </body></html>";
+
+    #[test]
+    fn blocks_become_lines() {
+        let dom = parse(PAGE, ParserOptions::default()).unwrap();
+        assert_eq!(
+            extract_text(&dom),
+            "Title\n\
+             First paragraph, spread over lines & with an inline link.\n\
+             A div\n\
+             after a break\n\
+             one\n\
+             two\n\
+             cell\n\
+             next\n"
+        );
+    }
+
+    #[test]
+    fn inline_elements_dont_split_words() {
+        let dom = parse("<p>wo<b>rd</b> <i>next</i></p>", ParserOptions::default()).unwrap();
+        assert_eq!(extract_text(&dom), "word next\n");
+    }
+
+    #[test]
+    fn readability_of_the_text() {
+        let text = "word ".repeat(450);
+        let figures = Readability::of(&text, text.len() * 4);
+        assert_eq!(figures.words, 450);
+        assert_eq!(figures.reading_minutes, 3);
+        assert_eq!(figures.text_ratio, 0.25);
+        assert_eq!(Readability::of("", 0).text_ratio, 0.0);
+    }
+}
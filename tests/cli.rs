@@ -0,0 +1,989 @@
+// Runs of the rget binary against a local server
+
+mod common;
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{free_port, get, rget, rget_with_env, spawn_rget, workdir, Server};
+
+#[test]
+fn export_urls_writes_every_link_and_image() {
+    let links: String = (1..=5)
+        .map(|i| format!("<a href=\"/page{}.html\">page {}</a>", i, i))
+        .collect();
+    let images: String = (1..=5)
+        .map(|i| format!("<img src=\"/img{}.png\">", i))
+        .collect();
+    let server = Server::new()
+        .page(
+            "/",
+            &format!("<html><body>{}{}</body></html>", links, images),
+        )
+        .start();
+    let dir = workdir("export-urls");
+
+    let out = rget(&dir, &["--export-urls", "urls.txt", &server.url("/")]);
+
+    assert!(out.status.success(), "{:?}", out);
+    let exported = std::fs::read_to_string(dir.join("urls.txt")).unwrap();
+    let exported: Vec<_> = exported.lines().collect();
+    assert_eq!(exported.len(), 10, "{:?}", exported);
+    for i in 1..=5 {
+        assert!(exported.contains(&server.url(&format!("/page{}.html", i)).as_str()));
+        assert!(exported.contains(&server.url(&format!("/img{}.png", i)).as_str()));
+    }
+    // urls are only listed, not downloaded
+    assert_eq!(server.hits("/img1.png"), 0);
+    assert_eq!(server.hits("/page1.html"), 0);
+}
+
+#[test]
+fn strip_comments_and_cdata() {
+    let page = "<html><!-- top --><head><title>t</title><!-- head --></head>\
+                <body><p>kept<!-- inline --></p><svg><![CDATA[ data ]]></svg>\
+                <!--\nmultiline\n--></body></html>";
+    assert_eq!(page.matches("<!--").count(), 4);
+    let server = Server::new().page("/", page).start();
+    let dir = workdir("strip-comments");
+
+    let out = rget(
+        &dir,
+        &["--strip-comments", "--strip-cdata", &server.url("/")],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let saved = std::fs::read_to_string(dir.join("127.0.0.1.html")).unwrap();
+    assert_eq!(saved.matches("<!--").count(), 0, "{}", saved);
+    assert!(!saved.contains("CDATA"), "{}", saved);
+    assert!(saved.contains("<title>t</title>"), "{}", saved);
+    assert!(saved.contains("<p>kept</p>"), "{}", saved);
+    assert!(saved.contains("<svg></svg>"), "{}", saved);
+}
+
+#[test]
+fn strip_scripts_leaves_an_inert_page() {
+    let page = "<html><head><script src=\"/app.js\"></script>\
+                <SCRIPT type=\"module\">import x from './x.js'</SCRIPT></head>\
+                <body onload=\"init()\"><a href=\"/\" onClick=\"steal()\">home</a>\
+                <img src=\"/x.png\" onerror=\"alert(1)\">\
+                <noscript><p>no js</p></noscript></body></html>";
+    let server = Server::new().page("/", page).start();
+    let dir = workdir("strip-scripts");
+
+    let out = rget(&dir, &["--strip-scripts", &server.url("/")]);
+
+    assert!(out.status.success(), "{:?}", out);
+    let saved = std::fs::read_to_string(dir.join("127.0.0.1.html"))
+        .unwrap()
+        .to_ascii_lowercase();
+    assert!(!saved.contains("<script"), "{}", saved);
+    for handler in ["onload", "onclick", "onerror"] {
+        assert!(!saved.contains(handler), "{}", saved);
+    }
+    assert!(
+        saved.contains("<noscript><p>no js</p></noscript>"),
+        "{}",
+        saved
+    );
+    assert!(saved.contains("<a href=\"/\">home</a>"), "{}", saved);
+}
+
+#[test]
+fn encoding_declaration_is_added() {
+    let server = Server::new()
+        .page(
+            "/",
+            "<html><head><title>t</title></head><body>x</body></html>",
+        )
+        .start();
+    let dir = workdir("encoding-declaration");
+
+    let out = rget(&dir, &["--output-encoding-declaration", &server.url("/")]);
+
+    assert!(out.status.success(), "{:?}", out);
+    let saved = std::fs::read_to_string(dir.join("127.0.0.1.html")).unwrap();
+    assert!(
+        saved.contains("<head><meta charset=\"UTF-8\"><title>t</title>"),
+        "{}",
+        saved
+    );
+}
+
+#[test]
+fn truncate_html_saves_at_most_n_bytes() {
+    // 43 bytes of markup, then two bytes per character in utf-8
+    let page = format!(
+        "<html><head><title>Big</title></head><body>{}</body></html>",
+        "é".repeat(100_000)
+    );
+    let server = Server::new().page("/", &page).start();
+    let dir = workdir("truncate-html");
+    let saved = dir.join("127.0.0.1.html");
+
+    for (n, expected) in [
+        ("100", 99),
+        ("99", 99),
+        ("1000", 999),
+        ("1001", 1001),
+        ("10", 10),
+    ] {
+        let out = rget(&dir, &["-m", "--truncate-html", n, &server.url("/")]);
+        assert!(out.status.success(), "{:?}", out);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(stderr.contains("images: 0"), "parsed in full: {}", stderr);
+        let body = std::fs::read(&saved).unwrap();
+        assert_eq!(body.len(), expected, "--truncate-html {}", n);
+        assert!(std::str::from_utf8(&body).is_ok());
+        assert!(page.as_bytes().starts_with(&body));
+        std::fs::remove_file(&saved).unwrap();
+    }
+}
+
+#[test]
+fn truncate_html_counts_bytes_of_the_output_encoding() {
+    // `日` is written as the 8 bytes of `&#26085;` in iso-8859-1
+    let page = format!("<html><body>{}</body></html>", "日".repeat(1000));
+    let server = Server::new().page("/", &page).start();
+    let dir = workdir("truncate-encoded");
+
+    let out = rget(
+        &dir,
+        &[
+            "--output-encoding",
+            "iso-8859-1",
+            "--truncate-html",
+            "100",
+            &server.url("/"),
+        ],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let body = std::fs::read_to_string(dir.join("127.0.0.1.html")).unwrap();
+    // 12 bytes of markup and 11 whole references
+    assert_eq!(body.len(), 12 + 11 * 8, "{}", body);
+    assert!(body.ends_with("&#26085;"), "{}", body);
+}
+
+#[test]
+fn fetch_summary_lists_saved_and_failed_urls() {
+    let server = Server::new()
+        .page(
+            "/",
+            "<html><body><a href=\"/a.html\">a</a><a href=\"/missing.html\">b</a></body></html>",
+        )
+        .page("/a.html", "<html><body>a</body></html>")
+        .start();
+    let dir = workdir("fetch-summary");
+
+    let out = rget(&dir, &["--crawl", "--fetch-summary", &server.url("/")]);
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("fetched: 2"), "{}", stderr);
+    assert!(
+        stderr.contains(&format!("200 {} => ", server.url("/a.html"))),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("errors: 1"), "{}", stderr);
+    assert!(stderr.contains(&server.url("/missing.html")), "{}", stderr);
+    assert!(stderr.contains("robots_blocked: 0"), "{}", stderr);
+    assert!(!stderr.contains("total_bytes: 0\n"), "{}", stderr);
+}
+
+#[test]
+fn metrics_are_served_during_the_crawl() {
+    let server = Server::new()
+        .page(
+            "/",
+            "<html><body><a href=\"/a.html\">a</a><a href=\"/slow.html\">slow</a></body></html>",
+        )
+        .page("/a.html", "<html><body>a</body></html>")
+        .page("/slow.html", "<html><body>slow</body></html>")
+        .slow("/slow.html", Duration::from_secs(3))
+        .start();
+    let dir = workdir("metrics");
+    let port = free_port().to_string();
+
+    let mut child = spawn_rget(
+        &dir,
+        &["--crawl", "--metrics-port", &port, &server.url("/")],
+    );
+    let port = port.parse().unwrap();
+    let deadline = Instant::now() + Duration::from_secs(3);
+    let scraped = loop {
+        let body = get(port, "/metrics").unwrap_or_default();
+        let counter = |name: &str| -> f64 {
+            body.lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or(0.0)
+        };
+        if counter("fetch_requests_total{status=\"success\"}") > 0.0
+            && counter("fetch_bytes_total") > 0.0
+        {
+            break body;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "counters stayed at zero: {}",
+            body
+        );
+        thread::sleep(Duration::from_millis(50));
+    };
+    // still waiting on /slow.html
+    let running = child.try_wait().unwrap().is_none();
+    let status = child.wait().unwrap();
+
+    assert!(running, "scraped after the run: {}", scraped);
+    assert!(status.success());
+}
+
+#[test]
+fn done_file_is_only_written_when_nothing_failed() {
+    let server = Server::new()
+        .page("/", "<html><body><a href=\"/a.html\">a</a></body></html>")
+        .page("/a.html", "<html><body>a</body></html>")
+        .start();
+    let dir = workdir("done-file");
+    let done = dir.join("done");
+
+    let out = rget(&dir, &["--crawl", "--done-file", "done", &server.url("/")]);
+    assert!(out.status.success(), "{:?}", out);
+    assert!(done.exists());
+    std::fs::remove_file(&done).unwrap();
+
+    let out = rget(
+        &dir,
+        &[
+            "--done-file",
+            "done",
+            &server.url("/"),
+            &server.url("/missing.html"),
+        ],
+    );
+    assert_eq!(out.status.code(), Some(1), "{:?}", out);
+    assert!(!done.exists());
+    // what could be fetched is still saved
+    assert!(dir.join("127.0.0.1.html").exists());
+}
+
+#[test]
+fn concurrent_requests_for_a_url_are_made_once() {
+    let server = Server::new()
+        .page("/", "<html><body>once</body></html>")
+        .slow("/", Duration::from_millis(300))
+        .page("/missing.html", "gone")
+        .status("/missing.html", "404 Not Found")
+        .slow("/missing.html", Duration::from_millis(300))
+        .start();
+    let dir = workdir("inflight");
+    let url = server.url("/");
+    let missing = server.url("/missing.html");
+    let mut args = vec!["--fetch-summary"];
+    args.extend([url.as_str(); 10]);
+    args.extend([missing.as_str(); 10]);
+
+    let out = rget(&dir, &args);
+
+    assert_eq!(server.hits("/"), 1);
+    assert_eq!(server.hits("/missing.html"), 1);
+    // the tasks that waited on the failed fetch failed with it
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("errors: 10"), "{}", stderr);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("127.0.0.1.html")).unwrap(),
+        "<html><body>once</body></html>"
+    );
+}
+
+#[test]
+fn noscript_fallback_images_are_fetched() {
+    let page = "<html><body><img class=\"lazy\" data-src=\"/fallback.png\">\
+                <noscript><img src=\"/fallback.png\"></noscript></body></html>";
+    let server = Server::new()
+        .page("/", page)
+        .file("/fallback.png", "image/png", b"png")
+        .start();
+    let dir = workdir("noscript");
+
+    let out = rget(&dir, &["-r", &server.url("/")]);
+
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(server.hits("/fallback.png"), 1);
+    let saved = std::fs::read_to_string(dir.join("127.0.0.1.html")).unwrap();
+    assert!(
+        saved.contains("<noscript><img src=\"127.0.0.1_fallback.png\">"),
+        "{}",
+        saved
+    );
+    assert_eq!(
+        std::fs::read(dir.join("127.0.0.1_fallback.png")).unwrap(),
+        b"png"
+    );
+}
+
+#[test]
+fn browser_headers_are_sent() {
+    let server = Server::new()
+        .page("/", "<html><body>chrome</body></html>")
+        .page("/firefox.html", "<html><body>firefox</body></html>")
+        .start();
+    let dir = workdir("browser-headers");
+
+    let out = rget(&dir, &["--browser", &server.url("/")]);
+    assert!(out.status.success(), "{:?}", out);
+    let out = rget(
+        &dir,
+        &[
+            "--simulate-browser",
+            "firefox",
+            "-H",
+            "DNT: 0",
+            &server.url("/firefox.html"),
+        ],
+    );
+    assert!(out.status.success(), "{:?}", out);
+
+    let chrome = &server.requests("/")[0];
+    assert!(
+        chrome.header("user-agent").unwrap().contains("Chrome/121"),
+        "{:?}",
+        chrome
+    );
+    assert_eq!(chrome.header("sec-fetch-dest"), Some("document"));
+    assert_eq!(chrome.header("sec-fetch-mode"), Some("navigate"));
+    assert_eq!(chrome.header("sec-fetch-site"), Some("none"));
+    assert_eq!(chrome.header("sec-fetch-user"), Some("?1"));
+    assert_eq!(chrome.header("sec-ch-ua-mobile"), Some("?0"));
+    assert_eq!(chrome.header("dnt"), Some("1"));
+
+    let firefox = &server.requests("/firefox.html")[0];
+    assert!(
+        firefox
+            .header("user-agent")
+            .unwrap()
+            .contains("Firefox/122"),
+        "{:?}",
+        firefox
+    );
+    assert_eq!(firefox.header("sec-fetch-mode"), Some("navigate"));
+    // firefox doesn't send client hints, and --header wins over the profile
+    assert_eq!(firefox.header("sec-ch-ua"), None);
+    assert_eq!(firefox.header("dnt"), Some("0"));
+}
+
+#[test]
+fn since_manifest_lists_the_changes() {
+    let server = Server::new()
+        .page("/", "<html><body>same</body></html>")
+        .page("/edited.html", "<html><body>edited</body></html>")
+        .page("/new.html", "<html><body>new</body></html>")
+        .start();
+    let dir = workdir("since-manifest");
+    let urls = [
+        server.url("/"),
+        server.url("/edited.html"),
+        server.url("/new.html"),
+    ];
+    let out = rget(
+        &dir,
+        &[
+            "--output-manifest",
+            "manifest.json",
+            &urls[0],
+            &urls[1],
+            &urls[2],
+        ],
+    );
+    assert!(out.status.success(), "{:?}", out);
+
+    // as if the earlier run had seen other content, no new.html, and a gone.html
+    let manifest = dir.join("manifest.json");
+    let mut entries: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert_eq!(entries.len(), 3);
+    entries.retain(|entry| entry["url"] != urls[2].as_str());
+    for entry in &mut entries {
+        if entry["url"] == urls[1].as_str() {
+            entry["sha256"] = "0".repeat(64).into();
+        }
+    }
+    let gone = server.url("/gone.html");
+    entries.push(serde_json::json!({
+        "url": gone,
+        "file": "127.0.0.1_gone.html",
+        "status": "ok",
+        "bytes": 4,
+        "sha256": "0".repeat(64),
+    }));
+    std::fs::write(&manifest, serde_json::to_string(&entries).unwrap()).unwrap();
+
+    let out = rget(
+        &dir,
+        &[
+            "--since-manifest",
+            "manifest.json",
+            &urls[0],
+            &urls[1],
+            &urls[2],
+        ],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let mut changelog: Vec<_> = stderr
+        .lines()
+        .filter(|line| {
+            ["ADDED: ", "CHANGED: ", "REMOVED: "]
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+        })
+        .collect();
+    changelog.sort();
+    let added = format!("ADDED: {}", urls[2]);
+    let changed = format!("CHANGED: {}", urls[1]);
+    let removed = format!("REMOVED: {}", gone);
+    assert_eq!(changelog, [&added, &changed, &removed], "{}", stderr);
+    assert!(
+        stderr.contains("added: 1\nchanged: 1\nunchanged: 1\nremoved: 1"),
+        "{}",
+        stderr
+    );
+    // replaced by this run's manifest
+    let entries: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(&manifest).unwrap()).unwrap();
+    let mut listed: Vec<_> = entries.iter().map(|e| e["url"].as_str().unwrap()).collect();
+    listed.sort();
+    let mut urls: Vec<_> = urls.iter().map(String::as_str).collect();
+    urls.sort();
+    assert_eq!(listed, urls);
+}
+
+#[test]
+fn slow_responses_are_reported() {
+    let server = Server::new()
+        .page("/", "<html><body>slow</body></html>")
+        .slow("/", Duration::from_millis(200))
+        .page("/fast.html", "<html><body>fast</body></html>")
+        .start();
+    let dir = workdir("slow");
+
+    let out = rget(
+        &dir,
+        &[
+            "--max-response-time-ms",
+            "100",
+            &server.url("/"),
+            &server.url("/fast.html"),
+        ],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let slow: Vec<_> = stderr.lines().filter(|l| l.starts_with("SLOW: ")).collect();
+    assert_eq!(slow.len(), 1, "{}", stderr);
+    assert!(
+        slow[0].starts_with(&format!("SLOW: {} took ", server.url("/"))),
+        "{}",
+        stderr
+    );
+    let ms: u64 = slow[0]
+        .rsplit(' ')
+        .next()
+        .and_then(|took| took.strip_suffix("ms"))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(ms >= 200, "{}", stderr);
+    assert!(stderr.contains("slow_urls: 1"), "{}", stderr);
+}
+
+#[test]
+fn stalled_downloads_are_abandoned() {
+    let server = Server::new()
+        .file("/big.bin", "application/octet-stream", &[b'x'; 4096])
+        .stall("/big.bin", 1024)
+        .start();
+    let dir = workdir("stall");
+
+    let started = Instant::now();
+    let out = rget(
+        &dir,
+        &[
+            "--timeout-per-byte",
+            "200",
+            "--fetch-summary",
+            &server.url("/big.bin"),
+        ],
+    );
+
+    assert!(started.elapsed() < Duration::from_secs(10), "{:?}", out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("errors: 1"), "{}", stderr);
+    assert!(
+        stderr.contains("download stalled, nothing received for 200ms after 1024 bytes"),
+        "{}",
+        stderr
+    );
+    assert!(!dir.join("127.0.0.1_big.bin").exists());
+}
+
+#[test]
+fn forms_are_posted() {
+    let server = Server::new().echo("/search").start();
+    let dir = workdir("post");
+
+    let out = rget(
+        &dir,
+        &["--post", &server.url("/search"), "q=rust+lang&page=2"],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let requests = server.requests("/search");
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].body, b"q=rust+lang&page=2");
+    assert_eq!(
+        requests[0].header("content-type"),
+        Some("application/x-www-form-urlencoded")
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("127.0.0.1_search")).unwrap(),
+        "<html><body>POST q=rust+lang&page=2</body></html>"
+    );
+}
+
+#[test]
+fn header_env_values_are_sent_but_not_traced() {
+    let server = Server::new()
+        .page("/", "<html><body>secret</body></html>")
+        .start();
+    let dir = workdir("header-env");
+
+    let out = rget_with_env(
+        &dir,
+        &[
+            "--header-env",
+            "X-Api-Key=RGET_TEST_API_KEY",
+            "--http-trace",
+            &server.url("/"),
+        ],
+        &[("RGET_TEST_API_KEY", "s3cr3t-value")],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(
+        server.requests("/")[0].header("x-api-key"),
+        Some("s3cr3t-value")
+    );
+    let trace = String::from_utf8_lossy(&out.stdout);
+    assert!(trace.contains("> x-api-key: [REDACTED]"), "{}", trace);
+    assert!(!trace.contains("s3cr3t-value"), "{}", trace);
+    assert!(!String::from_utf8_lossy(&out.stderr).contains("s3cr3t-value"));
+}
+
+#[test]
+fn pages_are_saved_in_the_output_encoding() {
+    let page = "<html><head><meta charset=\"utf-8\"></head>\
+                <body><p>Caf\u{e9} \u{a9} \u{65e5}</p></body></html>";
+    let server = Server::new().page("/", page).start();
+    let dir = workdir("output-encoding");
+
+    let out = rget(&dir, &["--output-encoding", "iso-8859-1", &server.url("/")]);
+
+    assert!(out.status.success(), "{:?}", out);
+    let saved = std::fs::read(dir.join("127.0.0.1.html")).unwrap();
+    // one byte per character that has one, the rest character references.
+    // tl closes the void <meta> when it serializes
+    let expected = b"<html><head><meta charset=\"windows-1252\"></meta></head>\
+                     <body><p>Caf\xe9 \xa9 &#26085;</p></body></html>";
+    assert_eq!(
+        String::from_utf8_lossy(&saved),
+        String::from_utf8_lossy(expected)
+    );
+    assert_eq!(saved, expected);
+}
+
+#[test]
+fn ignore_mime_parses_plain_text_as_html() {
+    let page = b"<html><body><a href=\"/linked.html\">linked</a></body></html>";
+    let server = Server::new()
+        .file("/page.txt", "text/plain", page)
+        .page("/linked.html", "<html><body>linked</body></html>")
+        .start();
+
+    let dir = workdir("mime-respected");
+    let out = rget(&dir, &["--crawl", &server.url("/page.txt")]);
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(server.hits("/linked.html"), 0);
+
+    let dir = workdir("ignore-mime");
+    let out = rget(
+        &dir,
+        &["--crawl", "--ignore-mime", &server.url("/page.txt")],
+    );
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(server.hits("/linked.html"), 1);
+    assert!(dir.join("127.0.0.1_linked.html").exists());
+}
+
+#[test]
+fn robots_blocked_urls_are_counted() {
+    let server = Server::new()
+        .page(
+            "/",
+            "<html><body><a href=\"/private/a.html\">a</a><a href=\"/private/b.html\">b</a>\
+             <a href=\"/public.html\">c</a></body></html>",
+        )
+        .file(
+            "/robots.txt",
+            "text/plain",
+            b"User-agent: *\nDisallow: /private/\n",
+        )
+        .page("/private/a.html", "<html><body>a</body></html>")
+        .page("/private/b.html", "<html><body>b</body></html>")
+        .page("/public.html", "<html><body>c</body></html>")
+        .start();
+    let dir = workdir("robots");
+
+    let out = rget(
+        &dir,
+        &[
+            "--crawl",
+            "--respect-robots",
+            "--fetch-summary",
+            &server.url("/"),
+        ],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(server.hits("/private/a.html"), 0);
+    assert_eq!(server.hits("/private/b.html"), 0);
+    assert_eq!(server.hits("/public.html"), 1);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("robots_skipped: 2\n"), "{}", stderr);
+    assert!(stderr.contains("robots_blocked: 2\n"), "{}", stderr);
+}
+
+#[test]
+fn user_agents_rotate_between_requests() {
+    let links: String = (1..=5)
+        .map(|i| format!("<a href=\"/page{}.html\">page {}</a>", i, i))
+        .collect();
+    let mut server = Server::new().page("/", &format!("<html><body>{}</body></html>", links));
+    for i in 1..=5 {
+        server = server.page(
+            &format!("/page{}.html", i),
+            "<html><body>page</body></html>",
+        );
+    }
+    let server = server.start();
+    let dir = workdir("user-agents");
+    std::fs::write(dir.join("agents.txt"), "agent-a\nagent-b\n").unwrap();
+
+    let out = rget(
+        &dir,
+        &[
+            "--crawl",
+            "--user-agent-file",
+            "agents.txt",
+            &server.url("/"),
+        ],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let paths = [
+        "/",
+        "/page1.html",
+        "/page2.html",
+        "/page3.html",
+        "/page4.html",
+        "/page5.html",
+    ];
+    let agents: Vec<_> = paths
+        .iter()
+        .map(|path| {
+            server.requests(path)[0]
+                .header("user-agent")
+                .unwrap()
+                .to_owned()
+        })
+        .collect();
+    // the pages are fetched concurrently, so only the split is known
+    let a = agents.iter().filter(|agent| *agent == "agent-a").count();
+    let b = agents.iter().filter(|agent| *agent == "agent-b").count();
+    assert_eq!((a, b), (3, 3), "{:?}", agents);
+}
+
+#[test]
+fn extract_links_only_prints_each_link_once() {
+    let server = Server::new()
+        .page(
+            "/",
+            "<html><head><link rel=\"canonical\" href=\"/\"><link rel=\"stylesheet\" href=\"style.css\">\
+             <script src=\"/app.js\"></script></head><body>\
+             <a href=\"a.html\">a</a><a href=\"/a.html\">a again</a>\
+             <a href=\"http://example.com/elsewhere\">external</a>\
+             <img src=\"/img.png\"><img src=\"img.png\"></body></html>",
+        )
+        .page(
+            "/a.html",
+            "<html><body><a href=\"/\">home</a><a href=\"/b.html\">b</a></body></html>",
+        )
+        .page("/b.html", "<html><body>b</body></html>")
+        .start();
+    let dir = workdir("extract-links");
+
+    let out = rget(&dir, &["--crawl", "--extract-links-only", &server.url("/")]);
+
+    assert!(out.status.success(), "{:?}", out);
+    let stdout = String::from_utf8_lossy(&out.stdout);
+    let mut printed: Vec<_> = stdout.lines().collect();
+    printed.sort();
+    let mut expected = vec![
+        server.url("/style.css"),
+        server.url("/app.js"),
+        server.url("/a.html"),
+        "http://example.com/elsewhere".to_owned(),
+        server.url("/img.png"),
+        server.url("/b.html"),
+    ];
+    expected.sort();
+    assert_eq!(printed, expected);
+    // links are followed, nothing is saved
+    assert_eq!(server.hits("/a.html"), 1);
+    assert_eq!(server.hits("/img.png"), 0);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn broken_links_are_reported() {
+    let server = Server::new()
+        .page(
+            "/",
+            "<html><body><a href=\"/ok.html\">fine</a><a href=\"/missing.html\">gone</a></body></html>",
+        )
+        .page("/ok.html", "<html><body>ok</body></html>")
+        .start();
+    let dir = workdir("check-links");
+
+    let out = rget(
+        &dir,
+        &[
+            "--check-links",
+            "--links-csv",
+            "links.csv",
+            &server.url("/"),
+        ],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let broken: Vec<_> = stderr
+        .lines()
+        .filter(|l| l.starts_with("[BROKEN]"))
+        .collect();
+    assert_eq!(
+        broken,
+        [format!(
+            "[BROKEN] {} -> {} (404)",
+            server.url("/"),
+            server.url("/missing.html")
+        )],
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("broken_links: 1\n"), "{}", stderr);
+    assert_eq!(server.requests("/missing.html")[0].method, "HEAD");
+    let csv = std::fs::read_to_string(dir.join("links.csv")).unwrap();
+    assert_eq!(
+        csv,
+        format!(
+            "source,target,text,rel,status\n{0},{1},fine,,200\n{0},{2},gone,,404\n",
+            server.url("/"),
+            server.url("/ok.html"),
+            server.url("/missing.html")
+        )
+    );
+}
+
+#[test]
+fn redirects_to_other_hosts_are_refused() {
+    let elsewhere = Server::new()
+        .page("/elsewhere.html", "<html><body>elsewhere</body></html>")
+        .start();
+    // a host name of its own, as redirects are checked by name
+    let other = elsewhere
+        .url("/elsewhere.html")
+        .replace("127.0.0.1", "localhost");
+    let server = Server::new()
+        .page("/moved.html", "<html><body>moved</body></html>")
+        .redirect("/same.html", "/moved.html")
+        .redirect("/cross.html", &other)
+        .start();
+    let dir = workdir("same-host-redirects");
+
+    let out = rget(
+        &dir,
+        &[
+            "--redirect-same-host-only",
+            "--fetch-summary",
+            &server.url("/same.html"),
+            &server.url("/cross.html"),
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(server.hits("/moved.html"), 1);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("127.0.0.1_same.html")).unwrap(),
+        "<html><body>moved</body></html>"
+    );
+    assert!(stderr.contains("errors: 1"), "{}", stderr);
+    assert!(
+        stderr.contains(&format!("redirected to another host: {}", other)),
+        "{}",
+        stderr
+    );
+    assert!(!dir.join("127.0.0.1_cross.html").exists());
+    assert_eq!(elsewhere.hits("/elsewhere.html"), 0);
+
+    // followed as usual without the flag
+    let dir = workdir("cross-host-redirects");
+    let out = rget(&dir, &[&server.url("/cross.html")]);
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(elsewhere.hits("/elsewhere.html"), 1);
+}
+
+#[test]
+fn rewrite_base_url_rebases_on_site_urls() {
+    let page = "<html><head><link rel=\"stylesheet\" href=\"//HOST/style.css\">\
+                <script src=\"http://HOST/js/app.js?v=2\"></script></head><body>\
+                <a href=\"http://HOST/dir/page.html?x=1#top\">on site</a>\
+                <a href=\"relative.html\">relative</a>\
+                <a href=\"https://other.example/page.html\">other site</a>\
+                <img src=\"http://HOST/img.png\"></body></html>";
+    // the page has to name the server's own address
+    let port = free_port();
+    let host = format!("127.0.0.1:{}", port);
+    let server = Server::new()
+        .page("/", &page.replace("HOST", &host))
+        .start_on(port);
+    let dir = workdir("rewrite-base");
+
+    let out = rget(
+        &dir,
+        &[
+            "--rewrite-base-url",
+            "https://mirror.example/base/",
+            &server.url("/"),
+        ],
+    );
+
+    assert!(out.status.success(), "{:?}", out);
+    let saved = std::fs::read_to_string(dir.join("127.0.0.1.html")).unwrap();
+    assert_eq!(
+        saved,
+        "<html><head><link rel=\"stylesheet\" href=\"https://mirror.example/base/style.css\"></link>\
+         <script src=\"https://mirror.example/base/js/app.js?v=2\"></script></head><body>\
+         <a href=\"https://mirror.example/base/dir/page.html?x=1#top\">on site</a>\
+         <a href=\"relative.html\">relative</a>\
+         <a href=\"https://other.example/page.html\">other site</a>\
+         <img src=\"https://mirror.example/base/img.png\"></img></body></html>"
+    );
+}
+
+#[test]
+fn requests_go_through_the_proxy_with_its_credentials() {
+    // a proxy sees the whole target url as the path
+    let proxy = Server::new()
+        .page("http://site.test/", "<html><body>proxied</body></html>")
+        .page("http://site.test/private.html", "denied")
+        .status(
+            "http://site.test/private.html",
+            "407 Proxy Authentication Required",
+        )
+        .start();
+    let dir = workdir("proxy");
+
+    let out = rget(
+        &dir,
+        &[
+            "--proxy",
+            &proxy.url(""),
+            "--proxy-auth",
+            "user:s3cret",
+            "--fetch-summary",
+            "http://site.test/",
+            "http://site.test/private.html",
+        ],
+    );
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let request = &proxy.requests("http://site.test/")[0];
+    // base64 of user:s3cret
+    assert_eq!(
+        request.header("proxy-authorization"),
+        Some("Basic dXNlcjpzM2NyZXQ=")
+    );
+    assert_eq!(request.header("host"), Some("site.test"));
+    assert_eq!(
+        std::fs::read_to_string(dir.join("site.test.html")).unwrap(),
+        "<html><body>proxied</body></html>"
+    );
+    // a proxy refusing the credentials fails the url
+    assert!(stderr.contains("errors: 1"), "{}", stderr);
+    assert!(stderr.contains("407"), "{}", stderr);
+    assert!(!dir.join("site.test_private.html").exists());
+
+    // without --proxy-auth nothing is sent to the proxy
+    let dir = workdir("proxy-no-auth");
+    let out = rget(&dir, &["--proxy", &proxy.url(""), "http://site.test/"]);
+    assert!(out.status.success(), "{:?}", out);
+    let request = &proxy.requests("http://site.test/")[1];
+    assert_eq!(request.header("proxy-authorization"), None);
+}
+
+#[test]
+fn randomise_order_shuffles_but_keeps_every_url() {
+    let paths: Vec<_> = (1..=20).map(|i| format!("/page{}.html", i)).collect();
+    let links: String = paths
+        .iter()
+        .map(|path| format!("<a href=\"{}\">{}</a>", path, path))
+        .collect();
+    let mut server = Server::new().page("/", &format!("<html><body>{}</body></html>", links));
+    for path in &paths {
+        server = server.page(path, "<html><body>page</body></html>");
+    }
+    let server = server.start();
+    let in_order: Vec<_> = paths.iter().map(|path| server.url(path)).collect();
+
+    // links are listed in the order they're queued
+    let dir = workdir("randomise-order");
+    let exported = |args: &[&str]| {
+        let out = rget(&dir, args);
+        assert!(out.status.success(), "{:?}", out);
+        let urls = std::fs::read_to_string(dir.join("urls.txt")).unwrap();
+        urls.lines().map(str::to_owned).collect::<Vec<_>>()
+    };
+    let seed = server.url("/");
+    assert_eq!(exported(&["--export-urls", "urls.txt", &seed]), in_order);
+    let shuffled = exported(&["--randomise-order", "--export-urls", "urls.txt", &seed]);
+    // 20! orders, this one coming out as is would be quite the coincidence
+    assert_ne!(shuffled, in_order);
+    let mut sorted = shuffled;
+    sorted.sort();
+    let mut expected = in_order;
+    expected.sort();
+    assert_eq!(sorted, expected);
+
+    let dir = workdir("randomise-order-crawl");
+    let out = rget(&dir, &["--randomise-order", "--crawl", &seed]);
+    assert!(out.status.success(), "{:?}", out);
+    for path in &paths {
+        assert_eq!(server.hits(path), 1, "{}", path);
+    }
+}
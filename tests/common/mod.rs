@@ -0,0 +1,297 @@
+// A small http server and helpers to run the rget binary against it
+
+#![allow(dead_code)]
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+struct Route {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+    delay: Duration,
+    // bytes of the body sent before the server goes quiet
+    stall_after: Option<usize>,
+    // answers with a page showing the method and body of the request
+    echo: bool,
+    location: Option<String>,
+}
+
+// What the server was asked for, header names are lowercased
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// Serves fixed responses on 127.0.0.1, recording the requests it gets
+#[derive(Default)]
+pub struct Server {
+    routes: HashMap<String, Route>,
+}
+
+pub struct Running {
+    addr: SocketAddr,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn page(self, path: &str, html: &str) -> Self {
+        self.file(path, "text/html; charset=utf-8", html.as_bytes())
+    }
+
+    pub fn file(mut self, path: &str, content_type: &'static str, body: &[u8]) -> Self {
+        self.routes.insert(
+            path.to_owned(),
+            Route {
+                status: "200 OK",
+                content_type,
+                body: body.to_vec(),
+                delay: Duration::ZERO,
+                stall_after: None,
+                echo: false,
+                location: None,
+            },
+        );
+        self
+    }
+
+    // answers `path` with `status`, e.g. "404 Not Found"
+    pub fn status(mut self, path: &str, status: &'static str) -> Self {
+        self.routes.get_mut(path).expect("no such route").status = status;
+        self
+    }
+
+    // answers `path` only after `delay`
+    pub fn slow(mut self, path: &str, delay: Duration) -> Self {
+        self.routes.get_mut(path).expect("no such route").delay = delay;
+        self
+    }
+
+    // redirects `path` to `location`, which may be on another server
+    pub fn redirect(self, path: &str, location: &str) -> Self {
+        let mut server = self.file(path, "text/plain", b"").status(path, "302 Found");
+        server.routes.get_mut(path).unwrap().location = Some(location.to_owned());
+        server
+    }
+
+    // answers `path` with "<METHOD> <body>" in a page, whatever was asked
+    pub fn echo(self, path: &str) -> Self {
+        let mut server = self.page(path, "");
+        server.routes.get_mut(path).unwrap().echo = true;
+        server
+    }
+
+    // sends the first `after` bytes of the body of `path`, then hangs
+    pub fn stall(mut self, path: &str, after: usize) -> Self {
+        self.routes
+            .get_mut(path)
+            .expect("no such route")
+            .stall_after = Some(after);
+        self
+    }
+
+    pub fn start(self) -> Running {
+        self.start_on(0)
+    }
+
+    // for pages that have to name the address they're served from
+    pub fn start_on(self, port: u16) -> Running {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let routes = Arc::new(self.routes);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let routes = routes.clone();
+                let requests = recorded.clone();
+                thread::spawn(move || serve(stream, &routes, &requests));
+            }
+        });
+        Running { addr, requests }
+    }
+}
+
+fn serve(mut stream: TcpStream, routes: &HashMap<String, Route>, requests: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    let mut headers = Vec::new();
+    let mut line = String::new();
+    while reader.read_line(&mut line).is_ok_and(|n| n > 2) {
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), value.trim().to_owned()));
+        }
+        line.clear();
+    }
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or("GET").to_owned();
+    let path = parts.next().unwrap_or("/").to_owned();
+    let echoed = format!(
+        "<html><body>{} {}</body></html>",
+        method,
+        String::from_utf8_lossy(&body)
+    );
+    requests.lock().unwrap().push(Request {
+        method,
+        path: path.clone(),
+        headers,
+        body,
+    });
+    let location = routes
+        .get(&path)
+        .and_then(|route| route.location.as_ref())
+        .map(|location| format!("Location: {}\r\n", location))
+        .unwrap_or_default();
+    let (status, content_type, body, delay, stall_after) = match routes.get(&path) {
+        Some(route) => (
+            route.status,
+            route.content_type,
+            if route.echo {
+                echoed.as_bytes()
+            } else {
+                &route.body[..]
+            },
+            route.delay,
+            route.stall_after,
+        ),
+        None => (
+            "404 Not Found",
+            "text/plain",
+            &b"not found"[..],
+            Duration::ZERO,
+            None,
+        ),
+    };
+    thread::sleep(delay);
+    let head = format!(
+        "HTTP/1.1 {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        location,
+        content_type,
+        body.len()
+    );
+    let _ = stream.write_all(head.as_bytes());
+    match stall_after {
+        Some(after) => {
+            let _ = stream.write_all(&body[..after]);
+            let _ = stream.flush();
+            // the connection stays open, with nothing more on it
+            thread::sleep(Duration::from_secs(60));
+        }
+        None => {
+            let _ = stream.write_all(body);
+        }
+    }
+}
+
+impl Running {
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    pub fn hits(&self, path: &str) -> usize {
+        self.requests(path).len()
+    }
+
+    // the requests made for `path` so far, oldest first
+    pub fn requests(&self, path: &str) -> Vec<Request> {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .iter()
+            .filter(|r| r.path == path)
+            .cloned()
+            .collect()
+    }
+}
+
+// An empty directory of its own for `name`, the binary writes into its cwd
+pub fn workdir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rget-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn rget(dir: &Path, args: &[&str]) -> Output {
+    rget_with_env(dir, args, &[])
+}
+
+pub fn rget_with_env(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rget"))
+        .args(args)
+        .envs(env.iter().copied())
+        .current_dir(dir)
+        .output()
+        .expect("failed to run rget")
+}
+
+// Starts the binary without waiting for it, its output is discarded
+pub fn spawn_rget(dir: &Path, args: &[&str]) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_rget"))
+        .args(args)
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("failed to run rget")
+}
+
+// A port nothing listens on right now
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+// The body of a plain GET, or None if nothing answers on `port`
+pub fn get(port: u16, path: &str) -> Option<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: 127.0.0.1\r\nConnection: close\r\n\r\n",
+        path
+    )
+    .ok()?;
+    let mut response = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut response).ok()?;
+    let (_, body) = response.split_once("\r\n\r\n")?;
+    Some(body.to_owned())
+}
@@ -0,0 +1,59 @@
+// The library api, as a program embedding rget would use it
+
+mod common;
+
+use std::time::Duration;
+
+use common::{workdir, Server};
+use reqwest::Url;
+use rget::{fetch_all, Config, FetchError};
+
+#[tokio::test]
+async fn fetch_all_summarizes_the_run() {
+    let server = Server::new()
+        .page(
+            "/",
+            "<html><body><a href=\"/a.html\">a</a><a href=\"/missing.html\">b</a></body></html>",
+        )
+        .page("/a.html", "<html><body>a</body></html>")
+        .slow("/a.html", Duration::from_millis(50))
+        .start();
+    let dir = workdir("fetch-all");
+    let out = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+    let config =
+        Config::from_args(["rget", "--crawl", "--output-template", &out("{name}")]).unwrap();
+
+    let seeds = vec![Url::parse(&server.url("/")).unwrap()];
+    let summary = fetch_all(seeds, config).await.unwrap();
+
+    assert_eq!(summary.fetched.len(), 2);
+    let a = summary
+        .fetched
+        .iter()
+        .find(|item| item.url.as_str() == server.url("/a.html"))
+        .expect("a.html wasn't fetched");
+    assert_eq!(a.status, 200);
+    assert_eq!(a.bytes, "<html><body>a</body></html>".len() as u64);
+    assert!(a.duration >= Duration::from_millis(50));
+    assert_eq!(
+        std::fs::read_to_string(&a.path).unwrap(),
+        "<html><body>a</body></html>"
+    );
+    assert_eq!(summary.errors.len(), 1);
+    match &summary.errors[0] {
+        FetchError::Http { url, status } => {
+            assert_eq!(url.as_str(), server.url("/missing.html"));
+            assert_eq!(status.as_u16(), 404);
+        }
+        e => panic!("unexpected error {}", e),
+    }
+    assert_eq!(summary.robots_blocked, 0);
+    assert!(summary.total_bytes > 0);
+    assert!(summary.elapsed >= a.duration);
+    assert!(!summary.interrupted);
+}
+
+#[test]
+fn invalid_command_lines_are_errors() {
+    assert!(Config::from_args(["rget", "--no-such-option"]).is_err());
+}